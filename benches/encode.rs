@@ -0,0 +1,97 @@
+//! Per-record cost of rendering lines for the log file
+//!
+//! `template/reparsed` is how lines used to be rendered, building a `TinyTemplate`
+//! and an owned context for every record. Run with `cargo bench --features bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use log::{Level, Record};
+use moe_logger::bench::Encoder;
+use moe_logger::{Encoding, LogConfig};
+use serde::Serialize;
+use std::process;
+use tinytemplate::{format_unescaped, TinyTemplate};
+
+const FORMAT: &str = "{t} [{thread}] {F}:{line} {L} {T} > {M}\n";
+const TIMESTAMP: &str = "2021-09-15T08:00:00.000Z";
+
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct OwnedContext<'a> {
+    L: String,
+    T: String,
+    M: String,
+    t: String,
+    F: &'a str,
+    K: String,
+    kv: serde_json::Map<String, serde_json::Value>,
+    line: Option<u32>,
+    module: &'a str,
+    thread: &'a str,
+    thread_id: usize,
+    pid: u32,
+    host: &'a str,
+    seq: u64,
+}
+
+fn reparsed(record: &Record) -> String {
+    let context = OwnedContext {
+        L: record.level().to_string(),
+        T: record.target().to_string(),
+        M: record.args().to_string(),
+        t: TIMESTAMP.to_string(),
+        F: record.file().unwrap_or(""),
+        K: String::new(),
+        kv: serde_json::Map::new(),
+        line: record.line(),
+        module: record.module_path().unwrap_or(""),
+        thread: "main",
+        thread_id: 0,
+        pid: process::id(),
+        host: "localhost",
+        seq: 0,
+    };
+    let mut tt = TinyTemplate::new();
+    tt.set_default_formatter(&format_unescaped);
+    tt.add_template("0", FORMAT).unwrap();
+    tt.render("0", &context).unwrap()
+}
+
+fn encoder(encoding: Encoding) -> Encoder {
+    let config = LogConfig::builder()
+        .format(FORMAT)
+        .encoding(encoding)
+        .finish();
+    Encoder::new(&config).unwrap()
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let args = format_args!("user {} logged in from {}", 42, "127.0.0.1");
+    let record = Record::builder()
+        .args(args)
+        .level(Level::Info)
+        .target("app::auth")
+        .module_path_static(Some("app::auth"))
+        .file_static(Some("src/auth.rs"))
+        .line(Some(42))
+        .build();
+
+    let mut group = c.benchmark_group("template");
+    group.bench_function("reparsed", |b| b.iter(|| reparsed(black_box(&record))));
+    let template = encoder(Encoding::Template);
+    group.bench_function("compiled", |b| {
+        b.iter(|| template.encode(black_box(&record), TIMESTAMP))
+    });
+    group.finish();
+
+    let json = encoder(Encoding::Json);
+    c.bench_function("json", |b| {
+        b.iter(|| json.encode(black_box(&record), TIMESTAMP))
+    });
+    let logfmt = encoder(Encoding::Logfmt);
+    c.bench_function("logfmt", |b| {
+        b.iter(|| logfmt.encode(black_box(&record), TIMESTAMP))
+    });
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+//! Encoder internals for the benchmarks in `benches/`, not part of the public API
+
+use crate::encode::{self, Fields, LineEncoder};
+use crate::{LogConfig, MoeLoggerError};
+use log::Record;
+
+/// Renders records the way lines are written to the log file
+pub struct Encoder(LineEncoder);
+
+impl Encoder {
+    pub fn new(config: &LogConfig) -> Result<Encoder, MoeLoggerError> {
+        Ok(Encoder(LineEncoder::new(config.encoding, &config.format)?))
+    }
+
+    pub fn encode(&self, record: &Record, timestamp: &str) -> String {
+        let fields = Fields::collect(record);
+        self.0
+            .encode(record, timestamp, &fields, encode::next_seq())
+            .unwrap_or_default()
+    }
+}
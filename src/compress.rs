@@ -0,0 +1,127 @@
+use crate::retention::{compression_temps, uncompressed_archives, with_suffix, Retention};
+use crate::Compression;
+use std::fs::{remove_file, rename, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+impl Compression {
+    /// Extension appended to compressed files
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => "gz",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => "zst",
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+    fn encode(self, input: &mut File, output: File) -> io::Result<File> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(output, flate2::Compression::default());
+                io::copy(input, &mut encoder)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(output, 0)?;
+                io::copy(input, &mut encoder)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Background thread compressing rotated files
+///
+/// Compressing a large file takes a while, doing it here keeps the writer thread free.
+/// Old files are pruned after each compression, so retention sees the final names.
+///
+/// With `recover`, it first cleans up after a run that stopped while compressing: half
+/// written temporary files are removed and rotated files left uncompressed are queued,
+/// oldest first. Only the first compressor of a process does that, one started on
+/// reload would race the compressor it replaces.
+pub(crate) struct Compressor {
+    sender: Option<Sender<PathBuf>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Compressor {
+    pub(crate) fn spawn(
+        compression: Compression,
+        output: PathBuf,
+        retention: Retention,
+        recover: bool,
+    ) -> io::Result<Compressor> {
+        let (sender, receiver) = channel::<PathBuf>();
+        if recover {
+            for temp in compression_temps(&output)? {
+                if let Err(e) = remove_file(&temp) {
+                    eprintln!("Failed to remove {}: {}", temp.display(), e);
+                }
+            }
+            for archive in uncompressed_archives(&output)? {
+                let _ = sender.send(archive);
+            }
+        }
+        let worker = thread::Builder::new()
+            .name("moe-logger-compress".to_string())
+            .spawn(move || {
+                for path in receiver {
+                    match compress_file(&path, compression) {
+                        Ok(()) => {}
+                        // Retention removed it while it was waiting.
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => eprintln!("Failed to compress log {}: {}", path.display(), e),
+                    }
+                    if let Err(e) = retention.prune(&output) {
+                        eprintln!("Failed to remove old logs: {}", e);
+                    }
+                }
+            })?;
+        Ok(Compressor {
+            sender: Some(sender),
+            worker: Some(worker),
+        })
+    }
+
+    /// Queue a rotated file for compression
+    pub(crate) fn compress(&self, path: PathBuf) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(path);
+        }
+    }
+}
+
+impl Drop for Compressor {
+    /// Wait for pending compressions, so no half written file is left behind
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Replace `path` by its compressed version
+///
+/// The data goes to a temporary file first, the original is only removed once the
+/// compressed file is complete. It keeps the modification time of the original, so
+/// retention still sees the oldest files first.
+fn compress_file(path: &Path, compression: Compression) -> io::Result<()> {
+    let target = with_suffix(path, compression.extension());
+    let temp = with_suffix(&target, "tmp");
+
+    let mut input = File::open(path)?;
+    let modified = input.metadata()?.modified()?;
+    let output = compression.encode(&mut input, File::create(&temp)?)?;
+    output.set_modified(modified)?;
+    output.sync_all()?;
+    rename(&temp, &target)?;
+    remove_file(path)
+}
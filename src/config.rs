@@ -0,0 +1,262 @@
+//! Loading `LogConfig` from config files and environment variables
+
+use crate::{
+    ColorChoice, Compression, Console, Encoding, LogConfig, MoeLoggerError, OpenMode,
+    OverflowPolicy, RotationPeriod, TargetWidth, TextStyle, Theme, Timestamp, Timezone,
+};
+use log::Level;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tinytemplate::TinyTemplate;
+
+/// Config as written by operators, every field is optional
+///
+/// Values go through `LogConfigBuilder`, so they are validated like the ones set in
+/// code.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RawConfig {
+    env: Option<String>,
+    #[serde(alias = "level")]
+    filter: Option<String>,
+    output: Option<PathBuf>,
+    #[serde(deserialize_with = "template")]
+    format: Option<String>,
+    encoding: Option<Encoding>,
+    timestamp: Option<Timestamp>,
+    timezone: Option<Timezone>,
+    console_timestamp: Option<bool>,
+    console: Option<Console>,
+    #[serde(deserialize_with = "template")]
+    console_format: Option<String>,
+    theme: Option<Theme>,
+    color_choice: Option<ColorChoice>,
+    target_width: Option<TargetWidth>,
+    rotation: Option<usize>,
+    rotation_size: Option<u64>,
+    rotation_period: Option<RotationPeriod>,
+    rotation_timezone: Option<Timezone>,
+    max_files: Option<usize>,
+    #[serde(deserialize_with = "duration")]
+    max_age: Option<Duration>,
+    max_total_bytes: Option<u64>,
+    compression: Option<Compression>,
+    overflow: Option<OverflowPolicy>,
+    open_mode: Option<OpenMode>,
+    sinks: Option<Vec<LogConfig>>,
+}
+
+impl From<RawConfig> for LogConfig {
+    fn from(raw: RawConfig) -> LogConfig {
+        let mut builder = LogConfig::builder();
+        if let Some(env) = raw.env {
+            builder = builder.env(env);
+        }
+        if let Some(filter) = raw.filter {
+            builder = builder.filter(filter);
+        }
+        if let Some(output) = raw.output {
+            builder = builder.output(output);
+        }
+        if let Some(format) = raw.format {
+            builder = builder.format(format);
+        }
+        if let Some(encoding) = raw.encoding {
+            builder = builder.encoding(encoding);
+        }
+        if let Some(timestamp) = raw.timestamp {
+            builder = builder.timestamp(timestamp);
+        }
+        if let Some(timezone) = raw.timezone {
+            builder = builder.timezone(timezone);
+        }
+        if let Some(console_timestamp) = raw.console_timestamp {
+            builder = builder.console_timestamp(console_timestamp);
+        }
+        if let Some(console) = raw.console {
+            builder = builder.console(console);
+        }
+        if let Some(console_format) = raw.console_format {
+            builder = builder.console_format(console_format);
+        }
+        if let Some(theme) = raw.theme {
+            builder = builder.theme(theme);
+        }
+        if let Some(color_choice) = raw.color_choice {
+            builder = builder.color_choice(color_choice);
+        }
+        if let Some(target_width) = raw.target_width {
+            builder = builder.target_width(target_width);
+        }
+        if let Some(rotation) = raw.rotation {
+            builder = builder.rotation(rotation);
+        }
+        if let Some(rotation_size) = raw.rotation_size {
+            builder = builder.rotation_size(rotation_size);
+        }
+        if let Some(period) = raw.rotation_period {
+            let timezone = raw.rotation_timezone.unwrap_or(Timezone::Utc);
+            builder = builder.rotation_period(period, timezone);
+        }
+        if let Some(max_files) = raw.max_files {
+            builder = builder.max_files(max_files);
+        }
+        if let Some(max_age) = raw.max_age {
+            builder = builder.max_age(max_age);
+        }
+        if let Some(max_total_bytes) = raw.max_total_bytes {
+            builder = builder.max_total_bytes(max_total_bytes);
+        }
+        if let Some(compression) = raw.compression {
+            builder = builder.compression(compression);
+        }
+        if let Some(overflow) = raw.overflow {
+            builder = builder.overflow(overflow);
+        }
+        if let Some(open_mode) = raw.open_mode {
+            builder = builder.open_mode(open_mode);
+        }
+        for sink in raw.sinks.into_iter().flatten() {
+            builder = builder.sink(sink);
+        }
+        builder.finish()
+    }
+}
+
+/// Theme changes on top of the default theme
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawTheme {
+    trace: Option<RawLevelStyle>,
+    debug: Option<RawLevelStyle>,
+    info: Option<RawLevelStyle>,
+    warn: Option<RawLevelStyle>,
+    error: Option<RawLevelStyle>,
+    target: Option<TextStyle>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawLevelStyle {
+    label: Option<TextStyle>,
+    message: Option<TextStyle>,
+}
+
+impl From<RawTheme> for Theme {
+    fn from(raw: RawTheme) -> Theme {
+        let levels = [
+            (Level::Trace, raw.trace),
+            (Level::Debug, raw.debug),
+            (Level::Info, raw.info),
+            (Level::Warn, raw.warn),
+            (Level::Error, raw.error),
+        ];
+        let mut theme = Theme::default();
+        for (level, style) in levels {
+            let style = match style {
+                Some(style) => style,
+                None => continue,
+            };
+            if let Some(label) = style.label {
+                theme = theme.level(level, label);
+            }
+            if let Some(message) = style.message {
+                theme = theme.message(level, message);
+            }
+        }
+        if let Some(target) = raw.target {
+            theme = theme.target(target);
+        }
+        theme
+    }
+}
+
+impl LogConfig {
+    /// Load a config file, the format is picked by its extension
+    ///
+    /// `.json` is always supported, `.toml` needs the `toml` feature and `.yaml` or
+    /// `.yml` the `yaml` feature.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<LogConfig, MoeLoggerError> {
+        let path = path.as_ref();
+        let error = |e: &dyn std::fmt::Display| {
+            MoeLoggerError::Config(format!("{}: {}", path.display(), e))
+        };
+        let text = fs::read_to_string(path).map_err(|e| error(&e))?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => serde_json::from_str(&text).map_err(|e| error(&e)),
+            #[cfg(feature = "toml")]
+            Some("toml") => toml::from_str(&text).map_err(|e| error(&e)),
+            #[cfg(feature = "yaml")]
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&text).map_err(|e| error(&e)),
+            _ => Err(error(&"unsupported config format")),
+        }
+    }
+
+    /// Load the config from environment variables like `MOE_LOG_OUTPUT`
+    ///
+    /// Names after the prefix are the config fields in upper case. Values that parse as
+    /// JSON, like `10000`, `true` or `{"pattern":"%H:%M"}`, are taken as such, anything
+    /// else as a string.
+    pub fn from_env_prefix(prefix: &str) -> Result<LogConfig, MoeLoggerError> {
+        let mut fields = Map::new();
+        for (name, value) in env::vars_os() {
+            let (name, value) = match (name.into_string(), value.into_string()) {
+                (Ok(name), Ok(value)) => (name, value),
+                _ => continue,
+            };
+            if let Some(field) = name.strip_prefix(prefix) {
+                let value = serde_json::from_str(&value).unwrap_or(Value::String(value));
+                fields.insert(field.to_lowercase(), value);
+            }
+        }
+        serde_json::from_value(Value::Object(fields))
+            .map_err(|e| MoeLoggerError::Config(format!("{}*: {}", prefix, e)))
+    }
+}
+
+/// A format TinyTemplate can parse
+fn template<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let format = String::deserialize(deserializer)?;
+    TinyTemplate::new()
+        .add_template("format", &format)
+        .map_err(|e| D::Error::custom(format!("invalid format: {}", e)))?;
+    Ok(Some(format))
+}
+
+/// Seconds, or a number with a unit like `30s`, `15m`, `12h`, `7d` or `2w`
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawDuration {
+        Seconds(u64),
+        Text(String),
+    }
+
+    let text = match RawDuration::deserialize(deserializer)? {
+        RawDuration::Seconds(seconds) => return Ok(Some(Duration::from_secs(seconds))),
+        RawDuration::Text(text) => text,
+    };
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let unit = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(D::Error::custom(format!("invalid duration: {}", text))),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit))
+        .map(|seconds| Some(Duration::from_secs(seconds)))
+        .ok_or_else(|| D::Error::custom(format!("invalid duration: {}", text)))
+}
@@ -0,0 +1,512 @@
+use crate::{Context, Encoding};
+use log::kv::{self, Key, VisitSource};
+use log::{Level, Record};
+use ouroboros::self_referencing;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::process;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use tinytemplate::error::Error;
+use tinytemplate::{format_unescaped, TinyTemplate};
+
+/// Number of the next record
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+static NEXT_THREAD_ID: AtomicUsize = AtomicUsize::new(0);
+static NEXT_TEMPLATE_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Threads are numbered in the order they first log
+    static THREAD_ID: usize = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    /// Formatting buffer reused by every record logged from this thread
+    static BUFFER: RefCell<String> = const { RefCell::new(String::new()) };
+    /// Templates parsed by this thread by id, TinyTemplate can't be shared between threads
+    static TEMPLATES: RefCell<HashMap<u64, Parsed>> = RefCell::new(HashMap::new());
+}
+
+/// Run `f` with this thread's formatting buffer, emptied
+///
+/// A record logged while formatting another one, like from a `Display` impl, gets a
+/// fresh buffer instead.
+fn with_buffer<T>(f: impl FnOnce(&mut String) -> T) -> T {
+    BUFFER.with(|buffer| match buffer.try_borrow_mut() {
+        Ok(mut buffer) => {
+            buffer.clear();
+            f(&mut buffer)
+        }
+        Err(_) => f(&mut String::new()),
+    })
+}
+
+/// Number a record, once for the console and the log file
+pub(crate) fn next_seq() -> u64 {
+    SEQUENCE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// `format` of an encoder, parsed once by each thread that renders it
+struct Template {
+    id: u64,
+    format: Arc<str>,
+}
+
+impl Template {
+    fn new(format: &str) -> Result<Template, Error> {
+        // Parse errors are reported when the logger starts.
+        compile(format)?;
+        Ok(Template {
+            id: NEXT_TEMPLATE_ID.fetch_add(1, Ordering::Relaxed),
+            format: format.into(),
+        })
+    }
+
+    fn render(&self, context: &Context) -> Result<String, Error> {
+        TEMPLATES.with(|templates| {
+            let mut templates = match templates.try_borrow_mut() {
+                Ok(templates) => templates,
+                Err(_) => return compile(&self.format)?.render("0", context),
+            };
+            if !templates.contains_key(&self.id) {
+                // A new encoder, likely after a reload, the ones it replaced are gone.
+                templates.retain(|_, parsed| parsed.borrow_encoder().strong_count() > 0);
+                let parsed = Parsed::try_new(
+                    Arc::downgrade(&self.format),
+                    self.format.to_string(),
+                    |format| compile(format),
+                )?;
+                templates.insert(self.id, parsed);
+            }
+            templates[&self.id].with_template(|template| template.render("0", context))
+        })
+    }
+}
+
+/// A template parsed by one thread, with its own copy of the text it borrows
+#[self_referencing]
+struct Parsed {
+    /// Dangles once the encoder is dropped, the entry is evicted then
+    encoder: Weak<str>,
+    format: String,
+    #[borrows(format)]
+    #[covariant]
+    template: TinyTemplate<'this>,
+}
+
+fn compile(format: &str) -> Result<TinyTemplate<'_>, Error> {
+    let mut tt = TinyTemplate::new();
+    tt.set_default_formatter(&format_unescaped);
+    tt.add_template("0", format)?;
+    Ok(tt)
+}
+
+/// Turns records into lines for the log file or the console
+pub(crate) struct LineEncoder {
+    encoding: Encoding,
+    template: Template,
+    /// Fields used by the template like `{kv.user_id}`, rendered empty when a record lacks them
+    template_fields: Vec<String>,
+    host: String,
+}
+
+impl LineEncoder {
+    pub(crate) fn new(encoding: Encoding, format: &str) -> Result<LineEncoder, Error> {
+        let template = match encoding {
+            Encoding::Template => Template::new(format)?,
+            // Structured encodings ignore `format`, don't fail on it.
+            _ => Template::new("")?,
+        };
+        Ok(LineEncoder {
+            encoding,
+            template,
+            template_fields: template_fields(format),
+            host: hostname::get()
+                .map(|host| host.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        })
+    }
+
+    pub(crate) fn encode(
+        &self,
+        record: &Record,
+        timestamp: &str,
+        fields: &Fields,
+        seq: u64,
+    ) -> Result<String, Error> {
+        match self.encoding {
+            Encoding::Template => self.render_template(record, timestamp, fields, seq),
+            Encoding::Json => with_buffer(|message| {
+                let _ = write!(message, "{}", record.args());
+                let line = JsonLine {
+                    timestamp,
+                    level: level_name(record.level()),
+                    target: record.target(),
+                    message,
+                    module_path: record.module_path(),
+                    file: record.file(),
+                    line: record.line(),
+                    fields: fields.to_map(),
+                };
+                let mut json = Vec::with_capacity(message.len() + LINE_OVERHEAD);
+                serde_json::to_writer(&mut json, &line)?;
+                json.push(b'\n');
+                // serde_json only writes valid UTF-8.
+                Ok(String::from_utf8(json).unwrap_or_default())
+            }),
+            Encoding::Logfmt => with_buffer(|message| {
+                let _ = write!(message, "{}", record.args());
+                let mut line = String::with_capacity(message.len() + LINE_OVERHEAD);
+                let _ = write_pair(&mut line, "ts", timestamp);
+                line.push(' ');
+                let _ = write_pair(&mut line, "level", level_name(record.level()));
+                line.push(' ');
+                let _ = write_pair(&mut line, "target", record.target());
+                line.push(' ');
+                let _ = write_pair(&mut line, "msg", message);
+                if let Some(file) = record.file() {
+                    line.push(' ');
+                    let _ = write_pair(&mut line, "file", file);
+                }
+                if !fields.is_empty() {
+                    line.push(' ');
+                    let _ = fields.write_pairs(&mut line, &LOGFMT_KEYS);
+                }
+                line.push('\n');
+                Ok(line)
+            }),
+        }
+    }
+
+    /// Render a sample record once, so unknown template variables are reported
+    /// when the logger starts instead of on every line
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.encoding != Encoding::Template {
+            return Ok(());
+        }
+        let record = Record::builder()
+            .level(Level::Info)
+            .target("moe_logger")
+            .build();
+        self.render_template(&record, "", &Fields::default(), 0)
+            .map(drop)
+    }
+
+    fn render_template(
+        &self,
+        record: &Record,
+        timestamp: &str,
+        fields: &Fields,
+        seq: u64,
+    ) -> Result<String, Error> {
+        let thread = thread::current();
+        let thread_id = THREAD_ID.with(|id| *id);
+        let thread_id_name;
+        let thread_name = match thread.name() {
+            Some(name) => name,
+            None => {
+                thread_id_name = thread_id.to_string();
+                &thread_id_name
+            }
+        };
+        with_buffer(|buffer| {
+            // Message and fields share the buffer, split after rendering both.
+            let _ = write!(buffer, "{}", record.args());
+            let message_len = buffer.len();
+            if !fields.is_empty() {
+                let _ = write!(buffer, "{}", fields);
+            }
+            let (message, fields_text) = buffer.split_at(message_len);
+            let context = Context {
+                L: record.level().as_str(),
+                T: record.target(),
+                M: message,
+                t: timestamp,
+                F: record.file().unwrap_or(""),
+                K: fields_text,
+                kv: self.template_map(fields),
+                line: record.line(),
+                module: record.module_path().unwrap_or(""),
+                thread: thread_name,
+                thread_id,
+                pid: process::id(),
+                host: &self.host,
+                seq,
+            };
+            self.template.render(&context)
+        })
+    }
+
+    fn template_map(&self, fields: &Fields) -> Map<String, Value> {
+        let mut map = fields.to_map();
+        for key in &self.template_fields {
+            map.entry(key.as_str())
+                .or_insert_with(|| Value::String(String::new()));
+        }
+        map
+    }
+}
+
+/// Names following `kv.` in a template
+fn template_fields(format: &str) -> Vec<String> {
+    format
+        .match_indices("kv.")
+        .map(|(index, prefix)| {
+            format[index + prefix.len()..]
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect::<String>()
+        })
+        .filter(|key| !key.is_empty())
+        .collect()
+}
+
+/// Key-value pairs attached to a record, like `info!(user_id = 42; "login")`
+///
+/// Displayed as logfmt pairs, `user_id=42`.
+#[derive(Default)]
+pub(crate) struct Fields(Vec<(String, Value)>);
+
+impl Fields {
+    pub(crate) fn collect(record: &Record) -> Fields {
+        let mut fields = Fields::default();
+        // Collecting into a Vec never fails.
+        let _ = record.key_values().visit(&mut fields);
+        fields
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn to_map(&self) -> Map<String, Value> {
+        self.0.iter().cloned().collect()
+    }
+}
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), json_value(&value)));
+        Ok(())
+    }
+}
+
+impl Fields {
+    /// Write the fields as logfmt pairs, keys in `reserved` get a `kv.` prefix
+    fn write_pairs<W: Write>(&self, out: &mut W, reserved: &[&str]) -> fmt::Result {
+        for (index, (key, value)) in self.0.iter().enumerate() {
+            if index > 0 {
+                out.write_char(' ')?;
+            }
+            let key = if reserved.contains(&key.as_str()) {
+                Cow::Owned(format!("kv.{}", key))
+            } else {
+                Cow::Borrowed(key.as_str())
+            };
+            let value = match value {
+                Value::String(value) => Cow::Borrowed(value.as_str()),
+                value => Cow::Owned(value.to_string()),
+            };
+            write_pair(out, &key, &value)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Fields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_pairs(f, &[])
+    }
+}
+
+/// Keep numbers and booleans typed, everything else becomes a string
+fn json_value(value: &kv::Value) -> Value {
+    if let Some(value) = value.to_bool() {
+        Value::from(value)
+    } else if let Some(value) = value.to_i64() {
+        Value::from(value)
+    } else if let Some(value) = value.to_u64() {
+        Value::from(value)
+    } else if let Some(value) = value.to_f64() {
+        Value::from(value)
+    } else if let Some(value) = value.to_borrowed_str() {
+        Value::from(value)
+    } else {
+        Value::from(value.to_string())
+    }
+}
+
+/// Keys of the pairs every logfmt line starts with
+const LOGFMT_KEYS: [&str; 5] = ["ts", "level", "target", "msg", "file"];
+
+/// Write a logfmt `key=value` pair, quoting the value when needed
+///
+/// Keys can't be quoted, characters that would end them become `_`.
+fn write_pair<W: Write>(out: &mut W, key: &str, value: &str) -> fmt::Result {
+    if key.is_empty() {
+        out.write_char('_')?;
+    }
+    for c in key.chars() {
+        match c {
+            ' ' | '=' | '"' => out.write_char('_')?,
+            c if c.is_control() => out.write_char('_')?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('=')?;
+
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c == ' ' || c == '=' || c == '"' || c == '\\' || c.is_control());
+    if !needs_quotes {
+        return out.write_str(value);
+    }
+
+    out.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp: &'a str,
+    level: &'static str,
+    target: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    module_path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+    /// Nested, so fields can't overwrite the keys above
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    fields: Map<String, Value>,
+}
+
+/// Room for everything but the message when sizing a structured line
+const LINE_OVERHEAD: usize = 128;
+
+/// Lowercase level names used by structured encodings
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Trace => "trace",
+        Level::Debug => "debug",
+        Level::Info => "info",
+        Level::Warn => "warn",
+        Level::Error => "error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(key: &str, value: &str) -> String {
+        let mut out = String::new();
+        write_pair(&mut out, key, value).unwrap();
+        out
+    }
+
+    #[test]
+    fn plain_values_are_not_quoted() {
+        assert_eq!(pair("msg", "started"), "msg=started");
+        assert_eq!(pair("path", "/var/log/app.log"), "path=/var/log/app.log");
+        assert_eq!(pair("name", "ünïcode"), "name=ünïcode");
+    }
+
+    #[test]
+    fn empty_values_are_quoted() {
+        assert_eq!(pair("msg", ""), r#"msg="""#);
+    }
+
+    #[test]
+    fn spaces_and_equal_signs_are_quoted() {
+        assert_eq!(pair("msg", "hello world"), r#"msg="hello world""#);
+        assert_eq!(pair("msg", "a=b"), r#"msg="a=b""#);
+    }
+
+    #[test]
+    fn quotes_and_backslashes_are_escaped() {
+        assert_eq!(pair("msg", r#"say "hi""#), r#"msg="say \"hi\"""#);
+        assert_eq!(pair("msg", r"C:\logs"), r#"msg="C:\\logs""#);
+    }
+
+    #[test]
+    fn control_characters_are_escaped() {
+        assert_eq!(pair("msg", "a\nb"), r#"msg="a\nb""#);
+        assert_eq!(pair("msg", "a\r\tb"), r#"msg="a\r\tb""#);
+        assert_eq!(pair("msg", "bell\u{7}"), r#"msg="bell\u0007""#);
+    }
+
+    #[test]
+    fn templates_go_with_their_encoder() {
+        let cached = || TEMPLATES.with(|templates| templates.borrow().len());
+        let record = Record::builder().args(format_args!("hello")).build();
+        let render =
+            |encoder: &LineEncoder| encoder.encode(&record, "", &Fields::default(), 0).unwrap();
+
+        let first = LineEncoder::new(Encoding::Template, "{M}\n").unwrap();
+        assert_eq!(render(&first), "hello\n");
+        assert_eq!(render(&first), "hello\n");
+        assert_eq!(cached(), 1);
+        drop(first);
+
+        let second = LineEncoder::new(Encoding::Template, "{M}!\n").unwrap();
+        assert_eq!(render(&second), "hello!\n");
+        assert_eq!(cached(), 1);
+    }
+
+    #[test]
+    fn keys_are_sanitized() {
+        assert_eq!(pair("user id", "1"), "user_id=1");
+        assert_eq!(pair("a=b\"c\n", "1"), "a_b_c_=1");
+        assert_eq!(pair("", "1"), "_=1");
+    }
+
+    fn encode(encoding: Encoding) -> String {
+        let fields: &[(&str, &str)] = &[("message", "spoofed"), ("level", "debug"), ("user", "x")];
+        let record = Record::builder()
+            .level(Level::Info)
+            .target("app")
+            .args(format_args!("real"))
+            .key_values(&fields)
+            .build();
+        let encoder = LineEncoder::new(encoding, "").unwrap();
+        encoder
+            .encode(&record, "now", &Fields::collect(&record), 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn json_fields_are_nested() {
+        let line: Value = serde_json::from_str(&encode(Encoding::Json)).unwrap();
+        assert_eq!(line["level"], "info");
+        assert_eq!(line["message"], "real");
+        assert_eq!(
+            line["fields"],
+            serde_json::json!({"message": "spoofed", "level": "debug", "user": "x"})
+        );
+    }
+
+    #[test]
+    fn logfmt_fields_do_not_shadow_builtin_keys() {
+        assert_eq!(
+            encode(Encoding::Logfmt),
+            "ts=now level=info target=app msg=real message=spoofed kv.level=debug user=x\n"
+        );
+    }
+}
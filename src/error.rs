@@ -0,0 +1,62 @@
+use log::SetLoggerError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Errors returned by `try_init`
+#[derive(Debug)]
+pub enum MoeLoggerError {
+    /// A logger has already been installed
+    AlreadyInitialized(SetLoggerError),
+    /// The log format can't be parsed or rendered
+    Template(tinytemplate::error::Error),
+    /// The timestamp pattern is invalid
+    Timestamp(String),
+    /// The log file can't be opened or written
+    Io(io::Error),
+    /// A config file or environment variable can't be read
+    Config(String),
+}
+
+impl fmt::Display for MoeLoggerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoeLoggerError::AlreadyInitialized(e) => write!(f, "Logger already initialized: {}", e),
+            MoeLoggerError::Template(e) => write!(f, "Invalid log format: {}", e),
+            MoeLoggerError::Timestamp(pattern) => {
+                write!(f, "Invalid timestamp pattern: {}", pattern)
+            }
+            MoeLoggerError::Io(e) => write!(f, "Failed to open log file: {}", e),
+            MoeLoggerError::Config(e) => write!(f, "Invalid config: {}", e),
+        }
+    }
+}
+
+impl Error for MoeLoggerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MoeLoggerError::AlreadyInitialized(e) => Some(e),
+            MoeLoggerError::Template(e) => Some(e),
+            MoeLoggerError::Timestamp(_) | MoeLoggerError::Config(_) => None,
+            MoeLoggerError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<SetLoggerError> for MoeLoggerError {
+    fn from(e: SetLoggerError) -> MoeLoggerError {
+        MoeLoggerError::AlreadyInitialized(e)
+    }
+}
+
+impl From<tinytemplate::error::Error> for MoeLoggerError {
+    fn from(e: tinytemplate::error::Error) -> MoeLoggerError {
+        MoeLoggerError::Template(e)
+    }
+}
+
+impl From<io::Error> for MoeLoggerError {
+    fn from(e: io::Error) -> MoeLoggerError {
+        MoeLoggerError::Io(e)
+    }
+}
@@ -1,26 +1,63 @@
-use env_logger::{
-    fmt::{Color, Style, StyledValue},
-    Builder,
-};
 use log::Level;
+#[cfg(feature = "config")]
+use serde::Deserialize;
 use serde::Serialize;
-use std::fmt;
-use std::fs::rename;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use tinytemplate::{format_unescaped, TinyTemplate};
-use tokio_uring::fs::OpenOptions;
-
-static WRITE_SEEK: AtomicUsize = AtomicUsize::new(0);
-static WRITE_LINE: AtomicUsize = AtomicUsize::new(0);
-static FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+mod compress;
+#[cfg(feature = "config")]
+mod config;
+mod encode;
+mod error;
+mod logger;
+mod period;
+mod retention;
+mod target;
+mod theme;
+mod timestamp;
+mod writer;
+
+pub use error::MoeLoggerError;
+use logger::Shared;
+
 static DEFAULT_TEMPLATE: &str = "{L} {T} > {M}\n";
 
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(from = "config::RawConfig")
+)]
 pub struct LogConfig {
-    pub env: &'static str,
-    pub output: &'static str,
+    pub env: String,
+    pub filter: String,
+    pub output: PathBuf,
     pub file: bool,
-    pub format: &'static str,
+    pub format: String,
+    pub encoding: Encoding,
+    pub timestamp: Timestamp,
+    pub timezone: Timezone,
+    pub console_timestamp: bool,
+    pub console: Console,
+    pub console_format: Option<String>,
+    pub theme: Theme,
+    pub color_choice: ColorChoice,
+    pub target_width: TargetWidth,
     pub rotation: usize,
+    pub rotation_size: u64,
+    pub rotation_period: Option<(RotationPeriod, Timezone)>,
+    pub max_files: usize,
+    pub max_age: Option<Duration>,
+    pub max_total_bytes: u64,
+    pub compression: Option<Compression>,
+    pub overflow: OverflowPolicy,
+    pub open_mode: OpenMode,
+    pub sinks: Vec<LogConfig>,
 }
 
 impl LogConfig {
@@ -28,130 +65,254 @@ impl LogConfig {
     pub fn builder() -> LogConfigBuilder {
         LogConfigBuilder::default()
     }
+}
 
+impl Default for LogConfig {
     /// Get a log config with default settings
     ///
     /// Default settings are:
-    /// ```
+    /// ```text
     /// LogConfig {
     ///     env: "RUST_LOG",
+    ///     filter: "info",
     ///     output: "stdout",
     ///     file: false,
     ///     format: DEFAULT_TEMPLATE,
+    ///     encoding: Encoding::Template,
+    ///     timestamp: Timestamp::Rfc3339(Precision::Millis),
+    ///     timezone: Timezone::Utc,
+    ///     console_timestamp: false,
+    ///     console: Console::Stderr,
+    ///     console_format: None,
+    ///     theme: Theme::default(),
+    ///     color_choice: ColorChoice::Auto,
+    ///     target_width: TargetWidth::default(),
     ///     rotation: 0,
+    ///     rotation_size: 0,
+    ///     rotation_period: None,
+    ///     max_files: 0,
+    ///     max_age: None,
+    ///     max_total_bytes: 0,
+    ///     compression: None,
+    ///     overflow: OverflowPolicy::Block,
+    ///     open_mode: OpenMode::Append,
+    ///     sinks: vec![],
     /// }
     /// ```
-    pub fn default() -> LogConfig {
+    fn default() -> LogConfig {
         LogConfigBuilder::default().into()
     }
 }
 
 pub struct LogConfigBuilder {
-    pub env: &'static str,
-    pub output: &'static str,
+    pub env: String,
+    pub filter: String,
+    pub output: PathBuf,
     pub file: bool,
-    pub format: &'static str,
+    pub format: String,
+    pub encoding: Encoding,
+    pub timestamp: Timestamp,
+    pub timezone: Timezone,
+    pub console_timestamp: bool,
+    pub console: Console,
+    pub console_format: Option<String>,
+    pub theme: Theme,
+    pub color_choice: ColorChoice,
+    pub target_width: TargetWidth,
     pub rotation: usize,
+    pub rotation_size: u64,
+    pub rotation_period: Option<(RotationPeriod, Timezone)>,
+    pub max_files: usize,
+    pub max_age: Option<Duration>,
+    pub max_total_bytes: u64,
+    pub compression: Option<Compression>,
+    pub overflow: OverflowPolicy,
+    pub open_mode: OpenMode,
+    pub sinks: Vec<LogConfig>,
 }
 
 impl LogConfigBuilder {
     /// Create a new log config builder with default settings
     ///
     /// Default settings are:
-    /// ```
+    /// ```text
     /// LogConfig {
     ///     env: "RUST_LOG",
+    ///     filter: "info",
     ///     output: "stdout",
     ///     file: false,
     ///     format: DEFAULT_TEMPLATE,
+    ///     encoding: Encoding::Template,
+    ///     timestamp: Timestamp::Rfc3339(Precision::Millis),
+    ///     timezone: Timezone::Utc,
+    ///     console_timestamp: false,
+    ///     console: Console::Stderr,
+    ///     console_format: None,
+    ///     theme: Theme::default(),
+    ///     color_choice: ColorChoice::Auto,
+    ///     target_width: TargetWidth::default(),
     ///     rotation: 0,
+    ///     rotation_size: 0,
+    ///     rotation_period: None,
+    ///     max_files: 0,
+    ///     max_age: None,
+    ///     max_total_bytes: 0,
+    ///     compression: None,
+    ///     overflow: OverflowPolicy::Block,
+    ///     open_mode: OpenMode::Append,
+    ///     sinks: vec![],
     /// }
     /// ```
     pub fn new() -> LogConfigBuilder {
         LogConfigBuilder {
-            env: "RUST_LOG",
-            output: "stdout",
+            env: "RUST_LOG".to_string(),
+            filter: "info".to_string(),
+            output: PathBuf::from("stdout"),
             file: false,
-            format: DEFAULT_TEMPLATE,
+            format: DEFAULT_TEMPLATE.to_string(),
+            encoding: Encoding::Template,
+            timestamp: Timestamp::Rfc3339(Precision::Millis),
+            timezone: Timezone::Utc,
+            console_timestamp: false,
+            console: Console::Stderr,
+            console_format: None,
+            theme: Theme::default(),
+            color_choice: ColorChoice::Auto,
+            target_width: TargetWidth::default(),
             rotation: 0,
+            rotation_size: 0,
+            rotation_period: None,
+            max_files: 0,
+            max_age: None,
+            max_total_bytes: 0,
+            compression: None,
+            overflow: OverflowPolicy::Block,
+            open_mode: OpenMode::Append,
+            sinks: Vec::new(),
         }
     }
 
     /// Set env viarable name for log level
     ///
     /// If this field is invalid, the default value of "RUST_LOG" will be used.
-    pub fn env(self, env: &'static str) -> LogConfigBuilder {
+    pub fn env(self, env: impl Into<String>) -> LogConfigBuilder {
         LogConfigBuilder {
-            env,
-            output: self.output,
-            file: self.file,
-            format: self.format,
-            rotation: self.rotation,
+            env: env.into(),
+            ..self
+        }
+    }
+
+    /// Set log filter used when the env variable isn't set
+    ///
+    /// Default value is "info". Takes the same directives as the env variable, like
+    /// "warn,my_app=debug".
+    pub fn filter(self, filter: impl Into<String>) -> LogConfigBuilder {
+        LogConfigBuilder {
+            filter: filter.into(),
+            ..self
         }
     }
 
     /// Set output destination for log
     ///
     /// Default value is "stdout". That means the output will not be written to any file.
-    /// Please ensure the output path is valid and not an existing file. Move old log file to another location before.
-    pub fn output(self, output: &'static str) -> LogConfigBuilder {
-        tokio_uring::start(async {
-            match OpenOptions::new()
-                .append(true)
-                .create_new(true)
-                .open(output)
-                .await
-            {
-                Ok(f) => {
-                    f.close().await.unwrap();
-                    LogConfigBuilder {
-                        env: self.env,
-                        output,
-                        file: true,
-                        format: self.format,
-                        rotation: self.rotation,
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Failed to open log file: {}", e);
-                    eprintln!("Moe Logger would only use stdout.");
-                    LogConfigBuilder {
-                        env: self.env,
-                        output: "stdout",
-                        file: false,
-                        format: self.format,
-                        rotation: self.rotation,
-                    }
-                }
-            }
-        })
+    /// The file is opened by `init`, see `open_mode` for what happens if it already exists.
+    pub fn output(self, output: impl Into<PathBuf>) -> LogConfigBuilder {
+        LogConfigBuilder {
+            output: output.into(),
+            file: true,
+            ..self
+        }
     }
 
     /// Set log format for lines written to file
     ///
-    /// Default value is "{L} {T} > {M}\n". Check README for detailed explanation.
-    pub fn format(self, format: &'static str) -> LogConfigBuilder {
-        let mut tt = TinyTemplate::new();
-        tt.add_template("default", DEFAULT_TEMPLATE).unwrap();
-        match tt.add_template("custom", format) {
-            Ok(_) => LogConfigBuilder {
-                env: self.env,
-                output: self.output,
-                file: self.file,
-                format,
-                rotation: self.rotation,
-            },
-            Err(e) => {
-                eprintln!("Failed to parse log format: {}", e);
-                eprintln!("Moe Logger would use default format.");
-                LogConfigBuilder {
-                    env: self.env,
-                    output: self.output,
-                    file: self.file,
-                    format: DEFAULT_TEMPLATE,
-                    rotation: self.rotation,
-                }
-            }
+    /// Default value is "{L} {T} > {M}\n". Check README for detailed explanation. An
+    /// invalid format is returned as `MoeLoggerError::Template` by `try_init` and `reload`.
+    pub fn format(self, format: impl Into<String>) -> LogConfigBuilder {
+        LogConfigBuilder {
+            format: format.into(),
+            ..self
+        }
+    }
+
+    /// Set encoding for lines written to file
+    ///
+    /// Default value is `Encoding::Template`, which renders `format`. `Encoding::Json`
+    /// and `Encoding::Logfmt` write structured lines and ignore `format`.
+    pub fn encoding(self, encoding: Encoding) -> LogConfigBuilder {
+        LogConfigBuilder { encoding, ..self }
+    }
+
+    /// Set how timestamps are written
+    ///
+    /// Default value is `Timestamp::Rfc3339(Precision::Millis)`, like `2021-09-15T08:00:00.000Z`.
+    pub fn timestamp(self, timestamp: Timestamp) -> LogConfigBuilder {
+        LogConfigBuilder { timestamp, ..self }
+    }
+
+    /// Set the timezone of timestamps
+    ///
+    /// Default value is `Timezone::Utc`. Local RFC3339 timestamps carry their offset.
+    pub fn timezone(self, timezone: Timezone) -> LogConfigBuilder {
+        LogConfigBuilder { timezone, ..self }
+    }
+
+    /// Set whether console lines start with a timestamp
+    ///
+    /// Default value is false. The timestamp uses the same format as the file. Use `{t}`
+    /// instead with `console_format`.
+    pub fn console_timestamp(self, console_timestamp: bool) -> LogConfigBuilder {
+        LogConfigBuilder {
+            console_timestamp,
+            ..self
+        }
+    }
+
+    /// Set where console lines are written
+    ///
+    /// Default value is `Console::Stderr`. `Console::Off` only writes to the log file.
+    pub fn console(self, console: Console) -> LogConfigBuilder {
+        LogConfigBuilder { console, ..self }
+    }
+
+    /// Set log format for console lines
+    ///
+    /// Default value is None, which writes colored lines like "INFO  app > message". The
+    /// template takes the same variables as `format` and is checked the same way.
+    pub fn console_format(self, console_format: impl Into<String>) -> LogConfigBuilder {
+        LogConfigBuilder {
+            console_format: Some(console_format.into()),
+            ..self
+        }
+    }
+
+    /// Set colors and styles of console lines
+    ///
+    /// Default value is `Theme::default()`. Only applies without `console_format`.
+    pub fn theme(self, theme: Theme) -> LogConfigBuilder {
+        LogConfigBuilder { theme, ..self }
+    }
+
+    /// Set whether console lines are colored
+    ///
+    /// Default value is `ColorChoice::Auto`, which disables colors when the console isn't a
+    /// terminal or `NO_COLOR` is set.
+    pub fn color_choice(self, color_choice: ColorChoice) -> LogConfigBuilder {
+        LogConfigBuilder {
+            color_choice,
+            ..self
+        }
+    }
+
+    /// Set the width of the target column on console lines
+    ///
+    /// Default value is `TargetWidth::default()`, which pads every target to the longest
+    /// one seen so far.
+    pub fn target_width(self, target_width: TargetWidth) -> LogConfigBuilder {
+        LogConfigBuilder {
+            target_width,
+            ..self
         }
     }
 
@@ -159,15 +320,99 @@ impl LogConfigBuilder {
     ///
     /// Default value is 0. That means no rotation.
     pub fn rotation(self, rotation: usize) -> LogConfigBuilder {
+        LogConfigBuilder { rotation, ..self }
+    }
+
+    /// Set file rotation size in bytes
+    ///
+    /// Default value is 0. That means no size based rotation. The file is rotated before
+    /// a line would make it larger than this size, it can be combined with `rotation`.
+    pub fn rotation_size(self, rotation_size: u64) -> LogConfigBuilder {
+        LogConfigBuilder {
+            rotation_size,
+            ..self
+        }
+    }
+
+    /// Set time based file rotation
+    ///
+    /// Default value is None. The file is rotated when a wall clock boundary in the given
+    /// timezone is crossed, and the rotated file is named after its period like
+    /// `output.log.2021-09-15`. Size and line rotations in the same period get a counter
+    /// appended to that name.
+    pub fn rotation_period(self, period: RotationPeriod, timezone: Timezone) -> LogConfigBuilder {
+        LogConfigBuilder {
+            rotation_period: Some((period, timezone)),
+            ..self
+        }
+    }
+
+    /// Set how many rotated files are kept
+    ///
+    /// Default value is 0. That means no limit. The oldest rotated files are deleted
+    /// after each rotation and when the logger starts.
+    pub fn max_files(self, max_files: usize) -> LogConfigBuilder {
+        LogConfigBuilder { max_files, ..self }
+    }
+
+    /// Set how long rotated files are kept
+    ///
+    /// Default value is None. That means no limit. Age is taken from the modification time.
+    pub fn max_age(self, max_age: Duration) -> LogConfigBuilder {
+        LogConfigBuilder {
+            max_age: Some(max_age),
+            ..self
+        }
+    }
+
+    /// Set how many bytes all rotated files may take together
+    ///
+    /// Default value is 0. That means no limit. The current log file is not counted.
+    pub fn max_total_bytes(self, max_total_bytes: u64) -> LogConfigBuilder {
+        LogConfigBuilder {
+            max_total_bytes,
+            ..self
+        }
+    }
+
+    /// Set compression for rotated files
+    ///
+    /// Default value is None. Rotated files are compressed on a background thread,
+    /// `output.log.3` becomes `output.log.3.gz` with `Compression::Gzip`.
+    pub fn compression(self, compression: Compression) -> LogConfigBuilder {
         LogConfigBuilder {
-            env: self.env,
-            output: self.output,
-            file: self.file,
-            format: self.format,
-            rotation,
+            compression: Some(compression),
+            ..self
         }
     }
 
+    /// Set what happens when the file writer falls behind
+    ///
+    /// Default value is `OverflowPolicy::Block`. Dropped records are counted and reported
+    /// as a WARN line in the log file, so gaps in the log are visible.
+    pub fn overflow(self, overflow: OverflowPolicy) -> LogConfigBuilder {
+        LogConfigBuilder { overflow, ..self }
+    }
+
+    /// Set how an existing log file is treated when the logger starts
+    ///
+    /// Default value is `OpenMode::Append`.
+    pub fn open_mode(self, open_mode: OpenMode) -> LogConfigBuilder {
+        LogConfigBuilder { open_mode, ..self }
+    }
+
+    /// Add another log file with its own filter, format and rotation
+    ///
+    /// Every record is offered to each sink, which writes it if its `filter` matches.
+    /// Only the file settings of `sink` are used, its `env` and console settings are
+    /// ignored. Every file needs an `output` of its own, and a sink can't have sinks.
+    /// See README for an example.
+    pub fn sink(self, sink: LogConfig) -> LogConfigBuilder {
+        let mut sinks = self.sinks;
+        sinks.push(sink);
+        LogConfigBuilder { sinks, ..self }
+    }
+
     pub fn finish(self) -> LogConfig {
         self.into()
     }
@@ -183,128 +428,514 @@ impl From<LogConfigBuilder> for LogConfig {
     fn from(builder: LogConfigBuilder) -> LogConfig {
         LogConfig {
             env: builder.env,
+            filter: builder.filter,
             output: builder.output,
             file: builder.file,
             format: builder.format,
+            encoding: builder.encoding,
+            timestamp: builder.timestamp,
+            timezone: builder.timezone,
+            console_timestamp: builder.console_timestamp,
+            console: builder.console,
+            console_format: builder.console_format,
+            theme: builder.theme,
+            color_choice: builder.color_choice,
+            target_width: builder.target_width,
             rotation: builder.rotation,
+            rotation_size: builder.rotation_size,
+            rotation_period: builder.rotation_period,
+            max_files: builder.max_files,
+            max_age: builder.max_age,
+            max_total_bytes: builder.max_total_bytes,
+            compression: builder.compression,
+            overflow: builder.overflow,
+            open_mode: builder.open_mode,
+            sinks: builder.sinks,
         }
     }
 }
 
+/// Encoding of lines written to the log file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Encoding {
+    /// Render the `format` template
+    Template,
+    /// JSON Lines with level, target, message, timestamp, file, line and module path
+    Json,
+    /// logfmt `key=value` pairs with timestamp, level, target, message and file
+    Logfmt,
+}
+
+/// Destination of console lines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Console {
+    Stdout,
+    Stderr,
+    /// Don't write to the console
+    Off,
+}
+
+/// Colors and styles of console lines
+///
+/// The default theme colors levels magenta, blue, green, yellow and red from TRACE to
+/// ERROR and makes the target bold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(from = "config::RawTheme")
+)]
+pub struct Theme {
+    pub trace: LevelStyle,
+    pub debug: LevelStyle,
+    pub info: LevelStyle,
+    pub warn: LevelStyle,
+    pub error: LevelStyle,
+    pub target: TextStyle,
+}
+
+impl Theme {
+    /// A theme without any color or style
+    pub fn plain() -> Theme {
+        Theme {
+            trace: LevelStyle::default(),
+            debug: LevelStyle::default(),
+            info: LevelStyle::default(),
+            warn: LevelStyle::default(),
+            error: LevelStyle::default(),
+            target: TextStyle::default(),
+        }
+    }
+
+    /// Set the style of a level name
+    pub fn level(mut self, level: Level, style: TextStyle) -> Theme {
+        self.level_style_mut(level).label = style;
+        self
+    }
+
+    /// Set the style of messages logged at a level
+    ///
+    /// Like `message(Level::Error, TextStyle::color(Color::Red))` to make errors stand out.
+    pub fn message(mut self, level: Level, style: TextStyle) -> Theme {
+        self.level_style_mut(level).message = style;
+        self
+    }
+
+    /// Set the style of the target
+    pub fn target(self, target: TextStyle) -> Theme {
+        Theme { target, ..self }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        let level = |color| LevelStyle {
+            label: TextStyle::color(color),
+            message: TextStyle::default(),
+        };
+        Theme {
+            trace: level(Color::Magenta),
+            debug: level(Color::Blue),
+            info: level(Color::Green),
+            warn: level(Color::Yellow),
+            error: level(Color::Red),
+            target: TextStyle::default().bold(),
+        }
+    }
+}
+
+/// Styles used for records of one level
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LevelStyle {
+    /// Style of the level name
+    pub label: TextStyle,
+    /// Style of the message
+    pub message: TextStyle,
+}
+
+/// Color and weight of a piece of a console line
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct TextStyle {
+    pub color: Option<Color>,
+    pub bold: bool,
+    pub dimmed: bool,
+}
+
+impl TextStyle {
+    /// A style with the given foreground color
+    pub fn color(color: Color) -> TextStyle {
+        TextStyle {
+            color: Some(color),
+            ..TextStyle::default()
+        }
+    }
+
+    pub fn bold(self) -> TextStyle {
+        TextStyle { bold: true, ..self }
+    }
+
+    pub fn dimmed(self) -> TextStyle {
+        TextStyle {
+            dimmed: true,
+            ..self
+        }
+    }
+}
+
+/// Width of the target column on console lines
+///
+/// Targets are padded to the longest one seen so far, bounded by `min` and `max`, so
+/// messages line up. With `per_line`, targets are only padded to `min`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct TargetWidth {
+    pub min: usize,
+    /// Longer targets are shortened, 0 means no limit
+    pub max: usize,
+    pub shorten: Shorten,
+    pub per_line: bool,
+}
+
+impl TargetWidth {
+    /// Pad targets to at least `min` characters and shorten them to at most `max`
+    pub fn new(min: usize, max: usize) -> TargetWidth {
+        TargetWidth {
+            min,
+            max,
+            ..TargetWidth::default()
+        }
+    }
+
+    /// Set how targets longer than `max` are shortened
+    pub fn shorten(self, shorten: Shorten) -> TargetWidth {
+        TargetWidth { shorten, ..self }
+    }
+
+    /// Don't line up messages with other lines
+    pub fn per_line(self) -> TargetWidth {
+        TargetWidth {
+            per_line: true,
+            ..self
+        }
+    }
+}
+
+/// How targets longer than the column are shortened
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Shorten {
+    /// Cut the end off, like `app::db::connection` to `app::db::co…`
+    #[default]
+    Truncate,
+    /// Shorten path segments to their initials from the left, like `app::db::connection_pool`
+    /// to `a::d::connection_pool`, then `a::d::c_p`
+    Abbreviate,
+}
+
+/// Terminal colors available to themes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+/// Whether console lines are colored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ColorChoice {
+    /// Color only when the console is a terminal and `NO_COLOR` isn't set
+    Auto,
+    /// Always color, like for CI logs that render ANSI colors
+    Always,
+    Never,
+}
+
+/// Policy applied when the queue in front of the file writer is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum OverflowPolicy {
+    /// Wait until the writer catches up
+    Block,
+    /// Drop the record being logged
+    DropNewest,
+    /// Drop the oldest queued record to make room
+    DropOldest,
+    /// Drop records less severe than the given level, wait for the others
+    DropBelow(Level),
+}
+
+/// Wall clock boundaries used for time based rotation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum RotationPeriod {
+    /// Every N minutes, counted from midnight, N can't be 0
+    Minutes(u32),
+    Hourly,
+    Daily,
+}
+
+/// Format of timestamps, the `t` template variable
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Timestamp {
+    /// RFC3339 like `2021-09-15T08:00:00.000Z`
+    Rfc3339(Precision),
+    /// strftime-like pattern like `%Y-%m-%d %H:%M:%S%.3f`, see chrono's `format::strftime`
+    Pattern(String),
+    /// Seconds since Unix epoch like `1631692800.000`
+    Unix(Precision),
+}
+
+/// Precision of the seconds in a timestamp
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Precision {
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Timezone {
+    Utc,
+    Local,
+}
+
+/// Compression applied to rotated files
+///
+/// Each variant needs the cargo feature of the same name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Compression {
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// What to do with an existing log file when the logger starts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum OpenMode {
+    /// Keep the old content and append new lines
+    Append,
+    /// Empty the file before writing
+    Truncate,
+    /// Refuse to start, `try_init` returns an error
+    Fail,
+    /// Move the old file into the rotation sequence and start a new one
+    Roll,
+}
+
 #[derive(Serialize)]
 #[allow(non_snake_case)]
 pub struct Context<'a> {
-    L: String,
-    T: String,
-    M: String,
-    t: String,
+    L: &'a str,
+    T: &'a str,
+    M: &'a str,
+    t: &'a str,
     F: &'a str,
+    K: &'a str,
+    kv: serde_json::Map<String, serde_json::Value>,
+    line: Option<u32>,
+    module: &'a str,
+    thread: &'a str,
+    thread_id: usize,
+    pid: u32,
+    host: &'a str,
+    seq: u64,
 }
 
-pub fn init(config: LogConfig) {
-    let mut builder = Builder::new();
-    let env_var = std::env::var(config.env).unwrap_or_else(|_| "info".to_string());
-
-    builder
-        .format(move |buf, record| {
-            use std::io::Write;
-            let target = record.target();
-            let max_width = max_target_width(target);
-
-            let mut style = buf.style();
-            let level = colored_level(&mut style, record.level());
-
-            let mut style = buf.style();
-            let target = style.set_bold(true).value(Padded {
-                value: target,
-                width: max_width,
-            });
-
-            let ret = writeln!(buf, "{} {} > {}", level, target, record.args());
-
-            if config.file {
-                tokio_uring::start(async {
-                    let context = Context {
-                        L: record.level().to_string(),
-                        T: record.target().to_string(),
-                        M: record.args().to_string(),
-                        t: buf.timestamp_millis().to_string(),
-                        F: record.file().unwrap_or(""),
-                    };
-                    let mut tt = TinyTemplate::new();
-                    tt.set_default_formatter(&format_unescaped);
-                    tt.add_template("0", config.format).unwrap();
-
-                    let lines = WRITE_LINE.load(Ordering::Relaxed) + 1;
-                    WRITE_LINE.store(lines, Ordering::Relaxed);
-
-                    let rendered = tt.render("0", &context).unwrap();
-                    let buf = rendered.as_bytes().to_vec();
-                    let file = OpenOptions::new()
-                        .append(true)
-                        .create(true)
-                        .open(config.output)
-                        .await
-                        .unwrap();
-                    let (res, _) = file
-                        .write_at(buf, WRITE_SEEK.load(Ordering::Relaxed) as u64)
-                        .await;
-                    if let Ok(res) = res {
-                        WRITE_SEEK.fetch_add(res, Ordering::SeqCst);
-                    }
-
-                    if lines == config.rotation {
-                        let file_num = FILE_COUNT.load(Ordering::Relaxed);
-                        let file_name = format!("{}.{}", config.output, file_num);
-                        match rename(config.output, file_name) {
-                            Ok(_) => {
-                                FILE_COUNT.fetch_add(1, Ordering::SeqCst);
-                                WRITE_LINE.store(0, Ordering::Relaxed);
-                            }
-                            Err(e) => {
-                                eprintln!("Failed to rotate log: {}", e);
-                            }
-                        }
-                    }
-                });
+/// Handle to the logger returned by `init`
+///
+/// Keep it alive until the end of `main`. When dropped, pending lines are written and
+/// the log file is synced to disk, so records logged right before exit are not lost.
+#[must_use = "dropping the handle flushes the log file right away, keep it until the end of main"]
+pub struct Handle {
+    shared: Arc<Shared>,
+}
+
+impl Handle {
+    /// Wait until every record logged so far is written and synced to disk
+    pub fn flush(&self) -> io::Result<()> {
+        self.shared.flush()
+    }
+
+    /// Write pending records, then close and reopen the log file
+    ///
+    /// Call it after an external tool like logrotate moved or truncated the file, so
+    /// logging continues at `output` and size based rotation counts from its new size.
+    pub fn reopen(&self) -> io::Result<()> {
+        self.shared.reopen()
+    }
+
+    /// Write pending records, sync the log file and stop the writer thread
+    ///
+    /// Records logged afterwards only go to the console.
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.shared.shutdown()
+    }
+
+    /// Replace the log filter while logging continues
+    ///
+    /// Takes the same directives as the env variable, like "info,my_app::db=debug". Sinks
+    /// keep their own filters.
+    pub fn set_filter(&self, filter: &str) {
+        self.shared.set_filter(filter)
+    }
+
+    /// Apply a new config while logging continues
+    ///
+    /// Filters, formats, rotation and every other setting change at once, records logged
+    /// meanwhile use either config. The log file is always reopened in append mode.
+    /// If the new config is invalid or its log file can't be opened, the previous config
+    /// stays in place and the error is returned. Otherwise the call returns once the
+    /// previous file writers stopped, after their pending compressions. A writer still
+    /// used by a slow record stops after that record instead.
+    pub fn reload(&self, config: LogConfig) -> Result<(), MoeLoggerError> {
+        self.shared.reload(config)
+    }
+
+    /// Reload the config from `path` whenever the process receives SIGHUP
+    ///
+    /// Errors are reported to stderr, the previous config stays in place then. Needs the
+    /// `signal` feature.
+    #[cfg(all(unix, feature = "signal"))]
+    pub fn reload_on_sighup(&self, path: impl Into<PathBuf>) -> io::Result<()> {
+        let path = path.into();
+        self.on_sighup(move |shared| {
+            let reloaded = LogConfig::from_file(&path).and_then(|config| shared.reload(config));
+            if let Err(e) = reloaded {
+                eprintln!("Failed to reload log config: {}", e);
             }
+        })
+    }
 
-            ret
+    /// Reopen the log file whenever the process receives SIGHUP
+    ///
+    /// Lets logrotate signal the process from `postrotate`. Errors are reported to
+    /// stderr. Needs the `signal` feature.
+    #[cfg(all(unix, feature = "signal"))]
+    pub fn reopen_on_sighup(&self) -> io::Result<()> {
+        self.on_sighup(|shared| {
+            if let Err(e) = shared.reopen() {
+                eprintln!("Failed to reopen log file: {}", e);
+            }
         })
-        .parse_filters(&env_var);
+    }
 
-    builder.try_init().unwrap()
-}
+    #[cfg(all(unix, feature = "signal"))]
+    fn on_sighup(&self, mut f: impl FnMut(&Shared) + Send + 'static) -> io::Result<()> {
+        use signal_hook::consts::SIGHUP;
+        use signal_hook::iterator::Signals;
 
-struct Padded<T> {
-    value: T,
-    width: usize,
+        let shared = self.shared.clone();
+        let mut signals = Signals::new([SIGHUP])?;
+        std::thread::Builder::new()
+            .name("moe-logger-signal".to_string())
+            .spawn(move || {
+                for _ in signals.forever() {
+                    f(&shared);
+                }
+            })?;
+        Ok(())
+    }
 }
 
-impl<T: fmt::Display> fmt::Display for Padded<T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{: <width$}", self.value, width = self.width)
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("Failed to flush log file: {}", e);
+        }
     }
 }
 
-static MAX_MODULE_WIDTH: AtomicUsize = AtomicUsize::new(0);
-
-fn max_target_width(target: &str) -> usize {
-    let max_width = MAX_MODULE_WIDTH.load(Ordering::Relaxed);
-    if max_width < target.len() {
-        MAX_MODULE_WIDTH.store(target.len(), Ordering::Relaxed);
-        target.len()
-    } else {
-        max_width
+/// Initialize the global logger
+///
+/// # Panics
+///
+/// Panics if a logger is already installed, the log format is invalid or the log file
+/// can't be opened. Use `try_init` to handle these errors.
+pub fn init(config: LogConfig) -> Handle {
+    match try_init(config) {
+        Ok(handle) => handle,
+        Err(e) => panic!("Failed to initialize Moe Logger: {}", e),
     }
 }
 
-fn colored_level<'a>(style: &'a mut Style, level: Level) -> StyledValue<'a, &'static str> {
-    match level {
-        Level::Trace => style.set_color(Color::Magenta).value("TRACE"),
-        Level::Debug => style.set_color(Color::Blue).value("DEBUG"),
-        Level::Info => style.set_color(Color::Green).value("INFO "),
-        Level::Warn => style.set_color(Color::Yellow).value("WARN "),
-        Level::Error => style.set_color(Color::Red).value("ERROR"),
-    }
+/// Initialize the global logger, returning an error instead of panicking
+///
+/// Nothing is opened if a logger is already installed. If the log file can't be opened,
+/// the logger stays installed but takes no records.
+pub fn try_init(config: LogConfig) -> Result<Handle, MoeLoggerError> {
+    let shared = Shared::install(config)?;
+    Ok(Handle { shared })
 }
@@ -0,0 +1,544 @@
+use crate::encode::{next_seq, Fields, LineEncoder};
+use crate::retention::is_archive_name;
+use crate::writer::FileWriter;
+use crate::{
+    Console, Encoding, LogConfig, MoeLoggerError, OpenMode, RotationPeriod, Timestamp, Timezone,
+};
+use env_logger::filter::{self, Filter};
+use env_logger::{Builder, Target};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long `reload` waits for records still using the old pipeline
+const RELOAD_GRACE: Duration = Duration::from_millis(100);
+
+/// The installed logger, whose pipeline can be replaced while logging continues
+pub(crate) struct Shared {
+    /// Only held to swap or clone the pipeline, records are formatted outside of it
+    pipeline: RwLock<Arc<Pipeline>>,
+    /// Writers replaced by `reload` while a record still used them
+    retired: Mutex<Vec<Arc<FileWriter>>>,
+}
+
+struct MoeLogger(Arc<Shared>);
+
+impl Log for MoeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.current().routes.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        // A `Display` impl may log too, or wait for a thread that does, so no lock is
+        // held while the record is formatted.
+        let pipeline = self.0.current();
+        if pipeline.routes.matches(record) {
+            pipeline.logger.log(record)
+        }
+    }
+
+    fn flush(&self) {
+        self.0.current().logger.flush();
+        if let Err(e) = self.0.flush() {
+            eprintln!("Failed to flush log file: {}", e);
+        }
+    }
+}
+
+impl Shared {
+    /// Start the pipeline and install it as the global logger
+    pub(crate) fn install(config: LogConfig) -> Result<Arc<Shared>, MoeLoggerError> {
+        let encoders = Encoders::new(&config)?;
+        // Claim the global logger before any file is touched, a second `try_init` must
+        // not truncate or roll the files of the first one.
+        let shared = Arc::new(Shared {
+            pipeline: RwLock::new(Arc::new(Pipeline::off())),
+            retired: Mutex::new(Vec::new()),
+        });
+        log::set_boxed_logger(Box::new(MoeLogger(shared.clone())))?;
+
+        let pipeline = Pipeline::new(config, encoders, true)?;
+        log::set_max_level(pipeline.routes.max_level());
+        shared.write(|current| *current = Arc::new(pipeline));
+        Ok(shared)
+    }
+
+    fn current(&self) -> Arc<Pipeline> {
+        self.pipeline
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The current writers and the retired ones, which may still have lines queued
+    fn writers(&self) -> Vec<Arc<FileWriter>> {
+        let retired = self.retired.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut writers = self.current().writers();
+        writers.extend(retired.iter().cloned());
+        writers
+    }
+
+    fn write<T>(&self, f: impl FnOnce(&mut Arc<Pipeline>) -> T) -> T {
+        f(&mut self
+            .pipeline
+            .write()
+            .unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        each_writer(&self.writers(), FileWriter::flush)
+    }
+
+    pub(crate) fn reopen(&self) -> io::Result<()> {
+        each_writer(&self.writers(), FileWriter::reopen)
+    }
+
+    pub(crate) fn shutdown(&self) -> io::Result<()> {
+        each_writer(&self.writers(), FileWriter::shutdown)
+    }
+
+    pub(crate) fn set_filter(&self, filter: &str) {
+        self.write(|pipeline| {
+            let new = pipeline.with_filter(filter);
+            log::set_max_level(new.routes.max_level());
+            *pipeline = Arc::new(new);
+        })
+    }
+
+    /// Replace the pipeline with one built from `config`
+    ///
+    /// Records logged meanwhile go to whichever pipeline they found. If the new one
+    /// can't be started, the previous one keeps running.
+    pub(crate) fn reload(&self, mut config: LogConfig) -> Result<(), MoeLoggerError> {
+        // The files are already in use, reloading must not truncate or roll them.
+        config.open_mode = OpenMode::Append;
+        for sink in &mut config.sinks {
+            sink.open_mode = OpenMode::Append;
+        }
+        let encoders = Encoders::new(&config)?;
+
+        let old = self.write(|pipeline| {
+            // Write everything the old writers have queued first, so lines stay in
+            // order and rotation starts from the final size of the files.
+            if let Err(e) = each_writer(&pipeline.writers(), FileWriter::flush) {
+                eprintln!("Failed to flush log file: {}", e);
+            }
+            let new = Pipeline::new(config, encoders, false)?;
+            log::set_max_level(new.routes.max_level());
+            Ok::<_, MoeLoggerError>(mem::replace(pipeline, Arc::new(new)))
+        })?;
+
+        // Records still being formatted hold the old writers through the pipeline they
+        // found, their lines go out before the writers stop. One of them may be waiting
+        // for this reload, so if they take too long the writers are retired and stopped
+        // by a later reload or `shutdown`.
+        let writers = old.writers();
+        drop(old);
+        let deadline = Instant::now() + RELOAD_GRACE;
+        while writers.iter().any(|writer| Arc::strong_count(writer) > 1)
+            && Instant::now() < deadline
+        {
+            thread::yield_now();
+        }
+
+        let mut retired = self.retired.lock().unwrap_or_else(PoisonError::into_inner);
+        retired.extend(writers);
+        let (stopped, busy): (Vec<_>, Vec<_>) = mem::take(&mut *retired)
+            .into_iter()
+            .partition(|writer| Arc::strong_count(writer) == 1);
+        *retired = busy;
+        drop(retired);
+
+        // Stopping the old writers waits for their pending compressions, records don't
+        // have to wait for that.
+        if let Err(e) = each_writer(&stopped, FileWriter::shutdown) {
+            eprintln!("Failed to close log file: {}", e);
+        }
+        Ok(())
+    }
+}
+
+/// Run `f` on every writer, the first error is returned after all of them ran
+fn each_writer(
+    writers: &[Arc<FileWriter>],
+    f: impl Fn(&FileWriter) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut result = Ok(());
+    for writer in writers {
+        let ret = f(writer);
+        if result.is_ok() {
+            result = ret;
+        }
+    }
+    result
+}
+
+/// The main log file if enabled, then every sink, with the filter each one uses
+fn files<'a>(
+    config: &'a LogConfig,
+    filter: &'a str,
+) -> impl Iterator<Item = (&'a LogConfig, &'a str)> {
+    let main = config.file.then_some((config, filter));
+    let sinks = config.sinks.iter().map(|sink| (sink, sink.filter.as_str()));
+    main.into_iter().chain(sinks)
+}
+
+/// Every file needs an output of its own, which no other file rotates over
+fn check_outputs(config: &LogConfig) -> Result<(), MoeLoggerError> {
+    let error = |message: String| Err(MoeLoggerError::Config(message));
+    if config.sinks.iter().any(|sink| !sink.sinks.is_empty()) {
+        return error("sinks can't have sinks of their own".to_string());
+    }
+
+    let mut outputs: Vec<(&Path, PathBuf)> = Vec::new();
+    for (file, _) in files(config, "") {
+        if !file.file {
+            return error("a sink has no output".to_string());
+        }
+        let output = resolve_dir(&file.output);
+        for (other, other_output) in &outputs {
+            if output == *other_output {
+                return error(format!(
+                    "{} and {} are the same file",
+                    other.display(),
+                    file.output.display()
+                ));
+            }
+            if is_archive_name(other_output, &output) || is_archive_name(&output, other_output) {
+                return error(format!(
+                    "{} and {} would be taken for rotated files of each other",
+                    other.display(),
+                    file.output.display()
+                ));
+            }
+        }
+        outputs.push((&file.output, output));
+    }
+    Ok(())
+}
+
+/// `path` in its canonical directory, so `./app.log` and `app.log` compare equal
+fn resolve_dir(path: &Path) -> PathBuf {
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => return path.to_path_buf(),
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    match fs::canonicalize(dir) {
+        Ok(dir) => dir.join(name),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// Templates are parsed once here and shared by every logging thread
+struct Encoders {
+    console: Option<Arc<LineEncoder>>,
+    files: Vec<Arc<LineEncoder>>,
+}
+
+impl Encoders {
+    /// Check everything that can be wrong with a config before anything is started
+    fn new(config: &LogConfig) -> Result<Encoders, MoeLoggerError> {
+        config
+            .timestamp
+            .validate()
+            .map_err(|pattern| MoeLoggerError::Timestamp(pattern.to_string()))?;
+
+        let console = match (config.console, config.console_format.as_deref()) {
+            (Console::Off, _) | (_, None) => None,
+            (_, Some(format)) => {
+                let encoder = LineEncoder::new(Encoding::Template, format)?;
+                encoder.validate()?;
+                Some(Arc::new(encoder))
+            }
+        };
+
+        check_outputs(config)?;
+        let mut encoders = Vec::new();
+        for (file, _) in files(config, "") {
+            file.timestamp
+                .validate()
+                .map_err(|pattern| MoeLoggerError::Timestamp(pattern.to_string()))?;
+            if let Some((RotationPeriod::Minutes(0), _)) = file.rotation_period {
+                return Err(MoeLoggerError::Config(format!(
+                    "{} rotates every 0 minutes",
+                    file.output.display()
+                )));
+            }
+            let encoder = LineEncoder::new(file.encoding, &file.format)?;
+            encoder.validate()?;
+            encoders.push(Arc::new(encoder));
+        }
+        Ok(Encoders {
+            console,
+            files: encoders,
+        })
+    }
+}
+
+/// Everything a record goes through, replaced as a whole on reload
+struct Pipeline {
+    config: Arc<LogConfig>,
+    console_encoder: Option<Arc<LineEncoder>>,
+    /// One writer per entry of `files`, in the same order
+    writers: Vec<(Arc<FileWriter>, Arc<LineEncoder>)>,
+    routes: Arc<Routes>,
+    logger: env_logger::Logger,
+}
+
+impl Pipeline {
+    /// Start a writer for every file, `recover` is passed on to `FileWriter::spawn`
+    fn new(
+        config: LogConfig,
+        encoders: Encoders,
+        recover: bool,
+    ) -> Result<Pipeline, MoeLoggerError> {
+        let filter = env::var(&config.env).unwrap_or_else(|_| config.filter.clone());
+        // Writers spawned before a failing one stop when dropped.
+        let writers = files(&config, &filter)
+            .zip(encoders.files)
+            .map(|((file, _), encoder)| {
+                let writer = FileWriter::spawn(file, encoder.clone(), recover)?;
+                Ok((Arc::new(writer), encoder))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let routes = Routes::new(&config, &filter, &writers);
+        let logger = build_logger(&config, &encoders.console, &routes);
+        Ok(Pipeline {
+            config: Arc::new(config),
+            console_encoder: encoders.console,
+            writers,
+            routes,
+            logger,
+        })
+    }
+
+    /// Takes no records, installed until the first pipeline is started
+    fn off() -> Pipeline {
+        let config = LogConfig {
+            console: Console::Off,
+            ..LogConfig::default()
+        };
+        let routes = Routes::new(&config, "off", &[]);
+        let logger = build_logger(&config, &None, &routes);
+        Pipeline {
+            config: Arc::new(config),
+            console_encoder: None,
+            writers: Vec::new(),
+            routes,
+            logger,
+        }
+    }
+
+    /// The same writers with `filter` in place of the main one
+    fn with_filter(&self, filter: &str) -> Pipeline {
+        let routes = Routes::new(&self.config, filter, &self.writers);
+        let logger = build_logger(&self.config, &self.console_encoder, &routes);
+        Pipeline {
+            config: self.config.clone(),
+            console_encoder: self.console_encoder.clone(),
+            writers: self.writers.clone(),
+            routes,
+            logger,
+        }
+    }
+
+    fn writers(&self) -> Vec<Arc<FileWriter>> {
+        self.writers
+            .iter()
+            .map(|(writer, _)| writer.clone())
+            .collect()
+    }
+}
+
+/// Which outputs take a record, rebuilt whenever a filter changes
+struct Routes {
+    console: Filter,
+    files: Vec<FileSink>,
+}
+
+/// A log file with its own filter, format and timestamps
+struct FileSink {
+    filter: Filter,
+    writer: Arc<FileWriter>,
+    encoder: Arc<LineEncoder>,
+    timestamp: Timestamp,
+    timezone: Timezone,
+}
+
+impl Routes {
+    fn new(
+        config: &LogConfig,
+        filter: &str,
+        writers: &[(Arc<FileWriter>, Arc<LineEncoder>)],
+    ) -> Arc<Routes> {
+        let console = match config.console {
+            Console::Off => parse_filter("off"),
+            Console::Stdout | Console::Stderr => parse_filter(filter),
+        };
+        let files = files(config, filter)
+            .zip(writers)
+            .map(|((file, filter), (writer, encoder))| FileSink {
+                filter: parse_filter(filter),
+                writer: writer.clone(),
+                encoder: encoder.clone(),
+                timestamp: file.timestamp.clone(),
+                timezone: file.timezone,
+            })
+            .collect();
+        Arc::new(Routes { console, files })
+    }
+
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+            || self.files.iter().any(|file| file.filter.enabled(metadata))
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        self.console.matches(record) || self.files.iter().any(|file| file.filter.matches(record))
+    }
+
+    /// The most verbose level any output takes
+    fn max_level(&self) -> LevelFilter {
+        self.files
+            .iter()
+            .map(|file| file.filter.filter())
+            .fold(self.console.filter(), Ord::max)
+    }
+}
+
+fn parse_filter(filter: &str) -> Filter {
+    filter::Builder::new().parse(filter).build()
+}
+
+/// Build the env_logger logger that formats console lines and feeds the file writers
+///
+/// It takes every record, `Routes` decides where each one goes.
+fn build_logger(
+    config: &LogConfig,
+    console_encoder: &Option<Arc<LineEncoder>>,
+    routes: &Arc<Routes>,
+) -> env_logger::Logger {
+    let (timestamp, timezone) = (config.timestamp.clone(), config.timezone);
+    let console_timestamp = config.console_timestamp;
+    let console = config.console;
+    let theme = config.theme;
+    let target_width = config.target_width;
+    let console_encoder = console_encoder.clone();
+    let routes = routes.clone();
+
+    let mut builder = Builder::new();
+    builder
+        .format(move |buf, record| {
+            use std::io::Write;
+            let now = SystemTime::now();
+            let write_console = routes.console.matches(record);
+            let timestamp = if write_console && (console_timestamp || console_encoder.is_some()) {
+                timestamp.format(timezone, now)
+            } else {
+                String::new()
+            };
+            let fields = Fields::collect(record);
+            let seq = next_seq();
+
+            let ret = match &console_encoder {
+                _ if !write_console => Ok(()),
+                Some(encoder) => match encoder.encode(record, &timestamp, &fields, seq) {
+                    Ok(line) => buf.write_all(line.as_bytes()),
+                    Err(e) => {
+                        eprintln!("Failed to render console line: {}", e);
+                        Ok(())
+                    }
+                },
+                None => {
+                    let target = target_width.fit(record.target());
+                    let width = target_width.pad_width(&target);
+                    let level_style = theme.level_style(record.level());
+
+                    let mut style = buf.style();
+                    let level = level_style
+                        .label
+                        .apply(&mut style)
+                        .value(level_label(record.level()));
+
+                    let mut style = buf.style();
+                    let target = theme.target.apply(&mut style).value(Padded {
+                        value: target,
+                        width,
+                    });
+
+                    if console_timestamp {
+                        write!(buf, "{} ", timestamp)?;
+                    }
+                    write!(buf, "{} {} > ", level, target)?;
+                    if level_style.message.is_plain() {
+                        write!(buf, "{}", record.args())?;
+                    } else {
+                        let mut style = buf.style();
+                        let message = level_style.message.apply(&mut style).value(record.args());
+                        write!(buf, "{}", message)?;
+                    }
+                    if fields.is_empty() {
+                        writeln!(buf)
+                    } else {
+                        writeln!(buf, " {}", fields)
+                    }
+                }
+            };
+
+            for file in &routes.files {
+                if !file.filter.matches(record) {
+                    continue;
+                }
+                let timestamp = file.timestamp.format(file.timezone, now);
+                match file.encoder.encode(record, &timestamp, &fields, seq) {
+                    Ok(line) => file.writer.write(record.level(), line),
+                    Err(e) => eprintln!("Failed to render log line: {}", e),
+                }
+            }
+
+            ret
+        })
+        .filter_level(LevelFilter::Trace)
+        .write_style(config.color_choice.write_style(console));
+    match console {
+        Console::Stdout => builder.target(Target::Stdout),
+        Console::Stderr => builder.target(Target::Stderr),
+        Console::Off => builder.target(Target::Pipe(Box::new(io::sink()))),
+    };
+    builder.build()
+}
+
+struct Padded<T> {
+    value: T,
+    width: usize,
+}
+
+impl<T: fmt::Display> fmt::Display for Padded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{: <width$}", self.value, width = self.width)
+    }
+}
+
+/// Level names padded to the same width
+fn level_label(level: Level) -> &'static str {
+    match level {
+        Level::Trace => "TRACE",
+        Level::Debug => "DEBUG",
+        Level::Info => "INFO ",
+        Level::Warn => "WARN ",
+        Level::Error => "ERROR",
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{RotationPeriod, Timezone};
+use chrono::{
+    DateTime, Duration, FixedOffset, Local, NaiveDateTime, Offset, TimeZone, Timelike, Utc,
+};
+use std::time::SystemTime;
+
+/// The rotation period a point in time falls into
+pub(crate) struct Period {
+    /// Start of the period, used to name the rotated file
+    pub(crate) label: String,
+    pub(crate) end: SystemTime,
+}
+
+impl Period {
+    pub(crate) fn at(period: RotationPeriod, timezone: Timezone, at: SystemTime) -> Period {
+        let utc = DateTime::<Utc>::from(at);
+        match timezone {
+            Timezone::Utc => {
+                let (start, end) = bounds(period, utc.naive_utc());
+                Period {
+                    label: label(period, start),
+                    end: Utc.from_utc_datetime(&end).into(),
+                }
+            }
+            Timezone::Local => {
+                let local = utc.with_timezone(&Local);
+                let (start, end) = bounds(period, local.naive_local());
+                Period {
+                    label: label(period, start),
+                    end: resolve_end(&Local, start, end, *local.offset()).into(),
+                }
+            }
+        }
+    }
+}
+
+/// When a period ending at local time `end` ends
+///
+/// An end inside a DST gap doesn't exist, it is taken with the offset in effect at
+/// `start`, the one before the clocks jump.
+fn resolve_end<Tz: TimeZone>(
+    timezone: &Tz,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    now: FixedOffset,
+) -> DateTime<Utc> {
+    if let Some(end) = timezone.from_local_datetime(&end).earliest() {
+        return end.with_timezone(&Utc);
+    }
+    let offset = timezone
+        .from_local_datetime(&start)
+        .earliest()
+        .map_or(now, |start| start.offset().fix());
+    Utc.from_utc_datetime(&(end - offset))
+}
+
+fn bounds(period: RotationPeriod, time: NaiveDateTime) -> (NaiveDateTime, NaiveDateTime) {
+    let midnight = time.date().and_hms_opt(0, 0, 0).unwrap();
+    let next_midnight = midnight + Duration::days(1);
+    match period {
+        RotationPeriod::Minutes(minutes) => {
+            // Periods restart at midnight when they don't divide a day evenly.
+            let minutes = i64::from(minutes);
+            let elapsed = i64::from(time.hour() * 60 + time.minute());
+            let start = midnight + Duration::minutes(elapsed / minutes * minutes);
+            (start, next_midnight.min(start + Duration::minutes(minutes)))
+        }
+        RotationPeriod::Hourly => {
+            let start = midnight + Duration::hours(i64::from(time.hour()));
+            (start, start + Duration::hours(1))
+        }
+        RotationPeriod::Daily => (midnight, next_midnight),
+    }
+}
+
+fn label(period: RotationPeriod, start: NaiveDateTime) -> String {
+    let format = match period {
+        RotationPeriod::Minutes(_) => "%Y-%m-%dT%H-%M",
+        RotationPeriod::Hourly => "%Y-%m-%dT%H",
+        RotationPeriod::Daily => "%Y-%m-%d",
+    };
+    start.format(format).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{MappedLocalTime, NaiveDate};
+
+    fn time(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 10)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn minutes_are_counted_from_midnight() {
+        let bounds = bounds(RotationPeriod::Minutes(45), time(1, 40));
+        assert_eq!(bounds, (time(1, 30), time(2, 15)));
+    }
+
+    #[test]
+    fn last_minutes_period_ends_at_midnight() {
+        let (start, end) = bounds(RotationPeriod::Minutes(50), time(23, 25));
+        assert_eq!(start, time(23, 20));
+        assert_eq!(end, time(0, 0) + Duration::days(1));
+    }
+
+    #[test]
+    fn hourly_and_daily() {
+        let hourly = bounds(RotationPeriod::Hourly, time(8, 59));
+        assert_eq!(hourly, (time(8, 0), time(9, 0)));
+        let daily = bounds(RotationPeriod::Daily, time(8, 59));
+        assert_eq!(daily, (time(0, 0), time(0, 0) + Duration::days(1)));
+    }
+
+    /// America/New_York around the start of DST on 2024-03-10, 02:00 EST jumps to
+    /// 03:00 EDT
+    #[derive(Clone, Debug)]
+    struct NewYork;
+
+    fn est() -> FixedOffset {
+        FixedOffset::west_opt(5 * 3600).unwrap()
+    }
+
+    fn edt() -> FixedOffset {
+        FixedOffset::west_opt(4 * 3600).unwrap()
+    }
+
+    impl TimeZone for NewYork {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> NewYork {
+            NewYork
+        }
+
+        fn offset_from_local_date(&self, _: &NaiveDate) -> MappedLocalTime<FixedOffset> {
+            unreachable!("not used by resolve_end")
+        }
+
+        fn offset_from_local_datetime(
+            &self,
+            local: &NaiveDateTime,
+        ) -> MappedLocalTime<FixedOffset> {
+            if *local < time(2, 0) {
+                MappedLocalTime::Single(est())
+            } else if *local < time(3, 0) {
+                MappedLocalTime::None
+            } else {
+                MappedLocalTime::Single(edt())
+            }
+        }
+
+        fn offset_from_utc_date(&self, _: &NaiveDate) -> FixedOffset {
+            unreachable!("not used by resolve_end")
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            if *utc < time(7, 0) {
+                est()
+            } else {
+                edt()
+            }
+        }
+    }
+
+    fn utc(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.from_utc_datetime(&time(hour, minute))
+    }
+
+    #[test]
+    fn end_outside_a_gap_is_local_time() {
+        let end = resolve_end(&NewYork, time(0, 45), time(1, 30), est());
+        assert_eq!(end, utc(6, 30));
+    }
+
+    #[test]
+    fn end_in_a_gap_uses_the_offset_of_start() {
+        // 02:15 EST is 03:15 EDT, after the current time instead of the day before.
+        let end = resolve_end(&NewYork, time(1, 30), time(2, 15), est());
+        assert_eq!(end, utc(7, 15));
+    }
+
+    #[test]
+    fn period_starting_in_a_gap_ends_in_local_time() {
+        let end = resolve_end(&NewYork, time(2, 30), time(3, 20), edt());
+        assert_eq!(end, utc(7, 20));
+    }
+}
@@ -0,0 +1,370 @@
+use crate::LogConfig;
+use std::fmt;
+use std::fs::{self, read_dir};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Limits on the rotated files kept next to the log file
+#[derive(Clone)]
+pub(crate) struct Retention {
+    max_files: usize,
+    max_age: Option<Duration>,
+    max_total_bytes: u64,
+}
+
+struct Archive {
+    path: PathBuf,
+    modified: SystemTime,
+    len: u64,
+}
+
+impl Retention {
+    pub(crate) fn new(config: &LogConfig) -> Retention {
+        Retention {
+            max_files: config.max_files,
+            max_age: config.max_age,
+            max_total_bytes: config.max_total_bytes,
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.max_files > 0 || self.max_age.is_some() || self.max_total_bytes > 0
+    }
+
+    /// Delete the oldest rotated files of `output` until every limit is met
+    ///
+    /// Rotated files are the siblings named like the logger names them, see
+    /// `is_archive_suffix`, including the ones left by previous runs.
+    pub(crate) fn prune(&self, output: &Path) -> io::Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let mut archives = archives(output)?;
+        // Newest first, so everything past a limit is older than what is kept. Files
+        // rotated within the same clock tick are ordered by name.
+        archives.sort_by_cached_key(|archive| {
+            std::cmp::Reverse((archive.modified, name_order(&archive.path)))
+        });
+
+        let now = SystemTime::now();
+        let mut total_bytes = 0;
+        for (index, archive) in archives.iter().enumerate() {
+            total_bytes += archive.len;
+            let too_many = self.max_files > 0 && index >= self.max_files;
+            let too_old = match self.max_age {
+                Some(max_age) => now
+                    .duration_since(archive.modified)
+                    .is_ok_and(|age| age > max_age),
+                None => false,
+            };
+            let too_large = self.max_total_bytes > 0 && total_bytes > self.max_total_bytes;
+
+            if too_many || too_old || too_large {
+                match fs::remove_file(&archive.path) {
+                    Ok(()) => {}
+                    // Pruned meanwhile by the writer replaced on reload.
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => {
+                        eprintln!("Failed to remove old log {}: {}", archive.path.display(), e)
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Next free number for rotated files named like `output.N`
+///
+/// Numbering continues after the highest one found, so a restart doesn't overwrite
+/// the files of a previous run. Compressed files are taken into account.
+pub(crate) fn next_index(output: &Path) -> io::Result<usize> {
+    let prefix = match archive_prefix(output) {
+        Some(prefix) => prefix,
+        None => return Ok(0),
+    };
+    let highest = archives(output)?
+        .iter()
+        .filter_map(|archive| {
+            let name = archive.path.file_name()?.to_str()?;
+            let suffix = name.strip_prefix(&prefix)?;
+            suffix.split('.').next()?.parse::<usize>().ok()
+        })
+        .max();
+    Ok(highest.map_or(0, |highest| highest + 1))
+}
+
+/// Part of a file name, numbers compare by value so `output.10` comes after `output.9`
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum NamePart {
+    Text(String),
+    Number(u64),
+}
+
+/// Sort key of a rotated file name, ignoring the compression extension
+fn name_order(path: &Path) -> Vec<NamePart> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    let name = strip_compression(name);
+
+    let mut parts = Vec::new();
+    let mut rest = name;
+    while let Some(c) = rest.chars().next() {
+        let is_digit = c.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (part, tail) = rest.split_at(end);
+        parts.push(match part.parse() {
+            Ok(number) if is_digit => NamePart::Number(number),
+            _ => NamePart::Text(part.to_string()),
+        });
+        rest = tail;
+    }
+    parts
+}
+
+/// `path` with `.suffix` appended, like `output.log.3` or `output.log.3.gz`
+pub(crate) fn with_suffix(path: &Path, suffix: impl fmt::Display) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", suffix));
+    PathBuf::from(name)
+}
+
+fn strip_compression(name: &str) -> &str {
+    name.strip_suffix(".gz")
+        .or_else(|| name.strip_suffix(".zst"))
+        .unwrap_or(name)
+}
+
+/// Whether a file named `output.<suffix>` is a rotated file
+///
+/// Rotated files are numbered like `output.3`, or named after their period like
+/// `output.2021-09-15` with an optional counter like `output.2021-09-15.2`. Either can
+/// be compressed, like `output.3.gz`. Other siblings are left alone.
+fn is_archive_suffix(suffix: &str) -> bool {
+    let suffix = strip_compression(suffix);
+    match suffix.split_once('.') {
+        Some((label, count)) => is_period_label(label) && is_number(count),
+        None => is_number(suffix) || is_period_label(suffix),
+    }
+}
+
+/// Whether `path` is named like a rotated file of `output`
+pub(crate) fn is_archive_name(output: &Path, path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str());
+    match (archive_prefix(output), name) {
+        (Some(prefix), Some(name)) if output.parent() == path.parent() => {
+            name.strip_prefix(&prefix).is_some_and(is_archive_suffix)
+        }
+        _ => false,
+    }
+}
+
+fn is_number(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Labels written by `Period`, a day, an hour or minutes
+fn is_period_label(text: &str) -> bool {
+    const SHAPES: [&str; 3] = ["0000-00-00", "0000-00-00T00", "0000-00-00T00-00"];
+    SHAPES.iter().any(|shape| {
+        shape.len() == text.len()
+            && shape.bytes().zip(text.bytes()).all(|(s, t)| match s {
+                b'0' => t.is_ascii_digit(),
+                s => s == t,
+            })
+    })
+}
+
+/// Rotated files of `output` are named like `output.*`
+fn archive_prefix(output: &Path) -> Option<String> {
+    let name = output.file_name()?.to_str()?;
+    Some(format!("{}.", name))
+}
+
+/// Rotated files of `output` that aren't compressed yet, oldest first
+pub(crate) fn uncompressed_archives(output: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut archives = siblings(output, |suffix| {
+        is_archive_suffix(suffix) && strip_compression(suffix) == suffix
+    })?;
+    archives.sort_by_cached_key(|archive| (archive.modified, name_order(&archive.path)));
+    Ok(archives.into_iter().map(|archive| archive.path).collect())
+}
+
+/// Temporary files of compressions that never finished, like `output.3.gz.tmp`
+pub(crate) fn compression_temps(output: &Path) -> io::Result<Vec<PathBuf>> {
+    let temps = siblings(output, |suffix| {
+        suffix
+            .strip_suffix(".tmp")
+            .is_some_and(|suffix| is_archive_suffix(suffix) && strip_compression(suffix) != suffix)
+    })?;
+    Ok(temps.into_iter().map(|temp| temp.path).collect())
+}
+
+fn archives(output: &Path) -> io::Result<Vec<Archive>> {
+    siblings(output, is_archive_suffix)
+}
+
+/// Files named `output.<suffix>` for which `matches(suffix)` holds
+fn siblings(output: &Path, matches: impl Fn(&str) -> bool) -> io::Result<Vec<Archive>> {
+    let prefix = match archive_prefix(output) {
+        Some(prefix) => prefix,
+        None => return Ok(Vec::new()),
+    };
+    let dir = match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut archives = Vec::new();
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let is_match = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.strip_prefix(&prefix).is_some_and(&matches));
+        if !is_match {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            archives.push(Archive {
+                path: entry.path(),
+                modified: metadata.modified()?,
+                len: metadata.len(),
+            });
+        }
+    }
+    Ok(archives)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::UNIX_EPOCH;
+
+    /// A fresh directory with `output` and files named `names`, the later ones newer
+    fn log_dir(test: &str, names: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "moe-logger-retention-{}-{}",
+            std::process::id(),
+            test
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        File::create(dir.join("app.log")).unwrap();
+        for (index, name) in names.iter().enumerate() {
+            let file = File::create(dir.join(name)).unwrap();
+            let modified = UNIX_EPOCH + Duration::from_secs(1_700_000_000 + index as u64);
+            file.set_modified(modified).unwrap();
+        }
+        dir
+    }
+
+    fn remaining(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    const UNRELATED: [&str; 6] = [
+        "app.log.keep",
+        "app.log.err",
+        "app.log.1.bak",
+        "app.log.2.gz.tmp",
+        "app.log.2024-03-10.x",
+        "app.logs.1",
+    ];
+
+    #[test]
+    fn archive_suffixes() {
+        for suffix in [
+            "3",
+            "3.gz",
+            "12.zst",
+            "2024-03-10",
+            "2024-03-10T08",
+            "2024-03-10T08-45",
+            "2024-03-10.2",
+            "2024-03-10T08.2.gz",
+        ] {
+            assert!(is_archive_suffix(suffix), "{}", suffix);
+        }
+        for suffix in [
+            "",
+            "keep",
+            "err",
+            "gz",
+            "1.bak",
+            "2.gz.tmp",
+            "1.2",
+            "2024-3-10",
+            "2024-03-10.",
+        ] {
+            assert!(!is_archive_suffix(suffix), "{}", suffix);
+        }
+    }
+
+    #[test]
+    fn next_index_skips_unrelated_files() {
+        let mut names = vec!["app.log.1", "app.log.4.gz", "app.log.2024-03-10.7"];
+        names.extend(UNRELATED);
+        names.push("app.log.99.bak");
+        let dir = log_dir("next-index", &names);
+        assert_eq!(next_index(&dir.join("app.log")).unwrap(), 5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn next_index_without_archives() {
+        let dir = log_dir("next-index-empty", &UNRELATED);
+        assert_eq!(next_index(&dir.join("app.log")).unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prune_keeps_newest_archives_and_unrelated_files() {
+        let mut names = UNRELATED.to_vec();
+        names.extend([
+            "app.log.1",
+            "app.log.2024-03-10.gz",
+            "app.log.2",
+            "app.log.3.gz",
+        ]);
+        let dir = log_dir("prune", &names);
+        let retention = Retention {
+            max_files: 2,
+            max_age: None,
+            max_total_bytes: 0,
+        };
+        retention.prune(&dir.join("app.log")).unwrap();
+
+        let mut expected = vec!["app.log", "app.log.2", "app.log.3.gz"];
+        expected.extend(UNRELATED);
+        expected.sort();
+        assert_eq!(remaining(&dir), expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prune_by_age() {
+        let dir = log_dir("prune-age", &["app.log.1", "app.log.keep"]);
+        let retention = Retention {
+            max_files: 0,
+            max_age: Some(Duration::from_secs(60)),
+            max_total_bytes: 0,
+        };
+        retention.prune(&dir.join("app.log")).unwrap();
+        assert_eq!(remaining(&dir), ["app.log", "app.log.keep"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
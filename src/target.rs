@@ -0,0 +1,133 @@
+use crate::{Shorten, TargetWidth};
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Widest target written so far, after shortening
+static MAX_TARGET_WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+impl TargetWidth {
+    /// Shorten a target longer than `max`
+    pub(crate) fn fit<'a>(&self, target: &'a str) -> Cow<'a, str> {
+        if self.max == 0 || width(target) <= self.max {
+            return Cow::Borrowed(target);
+        }
+        let target = match self.shorten {
+            Shorten::Truncate => target.into(),
+            Shorten::Abbreviate => abbreviate(target, self.max).into(),
+        };
+        truncate(target, self.max)
+    }
+
+    /// Width the target column is padded to for a fitted target
+    pub(crate) fn pad_width(&self, target: &str) -> usize {
+        if self.per_line {
+            return self.min;
+        }
+        let width = width(target);
+        let max_width = MAX_TARGET_WIDTH.fetch_max(width, Ordering::Relaxed);
+        max_width.max(width).max(self.min)
+    }
+}
+
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Replace path segments by their initials from the left until the target fits
+fn abbreviate(target: &str, max: usize) -> String {
+    let mut segments: Vec<Cow<str>> = target.split("::").map(Cow::Borrowed).collect();
+    let separators = 2 * (segments.len() - 1);
+    for index in 0..segments.len() {
+        let len = separators + segments.iter().map(|segment| width(segment)).sum::<usize>();
+        if len <= max {
+            break;
+        }
+        segments[index] = initials(&segments[index]).into();
+    }
+    segments.join("::")
+}
+
+/// `connection_pool` becomes `c_p`
+fn initials(segment: &str) -> String {
+    segment
+        .split('_')
+        .filter_map(|word| word.chars().next())
+        .fold(String::new(), |mut initials, c| {
+            if !initials.is_empty() {
+                initials.push('_');
+            }
+            initials.push(c);
+            initials
+        })
+}
+
+/// Cut a target to `max` characters, ending with an ellipsis
+fn truncate(target: Cow<'_, str>, max: usize) -> Cow<'_, str> {
+    if width(&target) <= max {
+        return target;
+    }
+    let mut short: String = target.chars().take(max.saturating_sub(1)).collect();
+    short.push('…');
+    short.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fit(width: TargetWidth, target: &str) -> String {
+        width.fit(target).into_owned()
+    }
+
+    #[test]
+    fn readme_examples() {
+        let width = TargetWidth::new(8, 10);
+        assert_eq!(fit(width, "a::b::very_long"), "a::b::ver…");
+        let width = width.shorten(Shorten::Abbreviate);
+        assert_eq!(fit(width, "a::b::very_long"), "a::b::v_l");
+    }
+
+    #[test]
+    fn short_targets_are_kept() {
+        let width = TargetWidth::new(8, 10);
+        assert_eq!(fit(width, "app::db"), "app::db");
+        assert_eq!(fit(width, "app::db::io"), "app::db::…");
+        let unlimited = TargetWidth::new(8, 0);
+        assert_eq!(fit(unlimited, "a::b::very_long"), "a::b::very_long");
+    }
+
+    #[test]
+    fn truncate_counts_characters() {
+        assert_eq!(truncate("héllo_wörld".into(), 6), "héllo…");
+        assert_eq!(truncate("héllo".into(), 5), "héllo");
+        assert_eq!(truncate("hello".into(), 1), "…");
+    }
+
+    #[test]
+    fn abbreviate_from_the_left() {
+        assert_eq!(
+            abbreviate("my_app::connection_pool::worker", 24),
+            "m_a::c_p::worker"
+        );
+        assert_eq!(
+            abbreviate("my_app::connection_pool::worker", 28),
+            "m_a::connection_pool::worker"
+        );
+        assert_eq!(abbreviate("my_app::db", 20), "my_app::db");
+    }
+
+    #[test]
+    fn abbreviate_may_not_be_enough() {
+        assert_eq!(abbreviate("app::very_long_name", 4), "a::v_l_n");
+        let width = TargetWidth::new(0, 4).shorten(Shorten::Abbreviate);
+        assert_eq!(fit(width, "app::very_long_name"), "a::…");
+    }
+
+    #[test]
+    fn initials_of_words() {
+        assert_eq!(initials("connection_pool"), "c_p");
+        assert_eq!(initials("db"), "d");
+        assert_eq!(initials("_private"), "p");
+        assert_eq!(initials(""), "");
+    }
+}
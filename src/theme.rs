@@ -0,0 +1,81 @@
+use crate::{Color, ColorChoice, Console, LevelStyle, TextStyle, Theme};
+use env_logger::fmt::{self, Style};
+use env_logger::WriteStyle;
+use log::Level;
+use std::env;
+use std::io::{self, IsTerminal};
+
+impl Theme {
+    pub(crate) fn level_style(&self, level: Level) -> &LevelStyle {
+        match level {
+            Level::Trace => &self.trace,
+            Level::Debug => &self.debug,
+            Level::Info => &self.info,
+            Level::Warn => &self.warn,
+            Level::Error => &self.error,
+        }
+    }
+
+    pub(crate) fn level_style_mut(&mut self, level: Level) -> &mut LevelStyle {
+        match level {
+            Level::Trace => &mut self.trace,
+            Level::Debug => &mut self.debug,
+            Level::Info => &mut self.info,
+            Level::Warn => &mut self.warn,
+            Level::Error => &mut self.error,
+        }
+    }
+}
+
+impl TextStyle {
+    /// Plain text is written without escape codes
+    pub(crate) fn is_plain(&self) -> bool {
+        *self == TextStyle::default()
+    }
+
+    pub(crate) fn apply<'a>(&self, style: &'a mut Style) -> &'a mut Style {
+        if let Some(color) = self.color {
+            style.set_color(color.into());
+        }
+        style.set_bold(self.bold).set_dimmed(self.dimmed)
+    }
+}
+
+impl From<Color> for fmt::Color {
+    fn from(color: Color) -> fmt::Color {
+        match color {
+            Color::Black => fmt::Color::Black,
+            Color::Red => fmt::Color::Red,
+            Color::Green => fmt::Color::Green,
+            Color::Yellow => fmt::Color::Yellow,
+            Color::Blue => fmt::Color::Blue,
+            Color::Magenta => fmt::Color::Magenta,
+            Color::Cyan => fmt::Color::Cyan,
+            Color::White => fmt::Color::White,
+        }
+    }
+}
+
+impl ColorChoice {
+    /// Resolve `Auto` against `NO_COLOR` and whether the console is a terminal
+    pub(crate) fn write_style(self, console: Console) -> WriteStyle {
+        let color = match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                !no_color
+                    && match console {
+                        Console::Stdout => io::stdout().is_terminal(),
+                        Console::Stderr => io::stderr().is_terminal(),
+                        Console::Off => false,
+                    }
+            }
+        };
+        if color {
+            WriteStyle::Always
+        } else {
+            WriteStyle::Never
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use crate::{Precision, Timestamp, Timezone};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+impl Timestamp {
+    /// Check a pattern before it is used on every line, chrono can't format invalid ones
+    pub(crate) fn validate(&self) -> Result<(), &str> {
+        match self {
+            Timestamp::Pattern(pattern) => {
+                if StrftimeItems::new(pattern).any(|item| matches!(item, Item::Error)) {
+                    Err(pattern)
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn format(&self, timezone: Timezone, at: SystemTime) -> String {
+        let utc = DateTime::<Utc>::from(at);
+        match self {
+            Timestamp::Rfc3339(precision) => {
+                let precision = seconds_format(*precision);
+                match timezone {
+                    Timezone::Utc => utc.to_rfc3339_opts(precision, true),
+                    Timezone::Local => utc.with_timezone(&Local).to_rfc3339_opts(precision, false),
+                }
+            }
+            Timestamp::Pattern(pattern) => {
+                let mut timestamp = String::new();
+                // Patterns are validated by `try_init`, formatting can't fail here.
+                let _ = match timezone {
+                    Timezone::Utc => write!(timestamp, "{}", utc.format(pattern)),
+                    Timezone::Local => {
+                        write!(timestamp, "{}", utc.with_timezone(&Local).format(pattern))
+                    }
+                };
+                timestamp
+            }
+            Timestamp::Unix(precision) => {
+                let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+                let secs = since_epoch.as_secs();
+                let nanos = since_epoch.subsec_nanos();
+                match *precision {
+                    Precision::Seconds => secs.to_string(),
+                    Precision::Millis => format!("{}.{:03}", secs, nanos / 1_000_000),
+                    Precision::Micros => format!("{}.{:06}", secs, nanos / 1_000),
+                    Precision::Nanos => format!("{}.{:09}", secs, nanos),
+                }
+            }
+        }
+    }
+}
+
+fn seconds_format(precision: Precision) -> SecondsFormat {
+    match precision {
+        Precision::Seconds => SecondsFormat::Secs,
+        Precision::Millis => SecondsFormat::Millis,
+        Precision::Micros => SecondsFormat::Micros,
+        Precision::Nanos => SecondsFormat::Nanos,
+    }
+}
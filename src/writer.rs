@@ -0,0 +1,513 @@
+use crate::compress::Compressor;
+use crate::encode::{self, Fields, LineEncoder};
+use crate::period::Period;
+use crate::retention::{next_index, with_suffix, Retention};
+use crate::{
+    Compression, LogConfig, OpenMode, OverflowPolicy, RotationPeriod, Timestamp, Timezone,
+};
+use log::{Level, Record};
+use std::collections::VecDeque;
+use std::fs::{rename, File, Metadata, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How many rendered lines may wait for the writer thread
+const QUEUE_CAPACITY: usize = 4096;
+/// How often the number of dropped records is written to the log file
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+enum Message {
+    Line(String),
+    /// Write everything queued before this message to disk, then acknowledge
+    Flush(SyncSender<io::Result<()>>),
+    /// Write everything queued before this message, then reopen the log file
+    Reopen(SyncSender<io::Result<()>>),
+}
+
+struct Queue {
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    policy: OverflowPolicy,
+    dropped: AtomicUsize,
+}
+
+struct QueueState {
+    messages: VecDeque<Message>,
+    closed: bool,
+}
+
+enum Pop {
+    Message(Message),
+    Timeout,
+    Closed,
+}
+
+impl Queue {
+    fn new(policy: OverflowPolicy) -> Queue {
+        Queue {
+            state: Mutex::new(QueueState {
+                messages: VecDeque::with_capacity(QUEUE_CAPACITY),
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            policy,
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    fn push_line(&self, level: Level, line: String) {
+        let mut state = self.state.lock().unwrap();
+        while state.messages.len() >= QUEUE_CAPACITY && !state.closed {
+            let wait = match self.policy {
+                OverflowPolicy::Block => true,
+                OverflowPolicy::DropNewest => false,
+                OverflowPolicy::DropOldest => {
+                    let oldest = state
+                        .messages
+                        .iter()
+                        .position(|message| matches!(message, Message::Line(_)));
+                    if let Some(oldest) = oldest {
+                        state.messages.remove(oldest);
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    break;
+                }
+                OverflowPolicy::DropBelow(threshold) => level <= threshold,
+            };
+            if !wait {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            state = self.not_full.wait(state).unwrap();
+        }
+        if state.closed {
+            return;
+        }
+        state.messages.push_back(Message::Line(line));
+        drop(state);
+        self.not_empty.notify_one();
+    }
+
+    /// Queue a control message, these are never dropped nor blocked by a full queue
+    ///
+    /// Returns false if the writer has been shut down.
+    fn push_control(&self, message: Message) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        state.messages.push_back(message);
+        drop(state);
+        self.not_empty.notify_one();
+        true
+    }
+
+    fn try_pop(&self) -> Option<Message> {
+        let message = self.state.lock().unwrap().messages.pop_front();
+        if message.is_some() {
+            self.not_full.notify_one();
+        }
+        message
+    }
+
+    fn pop_timeout(&self, timeout: Duration) -> Pop {
+        let mut state = self.state.lock().unwrap();
+        if state.messages.is_empty() && !state.closed {
+            state = self.not_empty.wait_timeout(state, timeout).unwrap().0;
+        }
+        match state.messages.pop_front() {
+            Some(message) => {
+                drop(state);
+                self.not_full.notify_one();
+                Pop::Message(message)
+            }
+            None if state.closed => Pop::Closed,
+            None => Pop::Timeout,
+        }
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+}
+
+/// Handle to the background thread that owns the log file
+///
+/// Records are rendered on the logging thread and pushed into a bounded queue,
+/// the writer thread keeps the file open for the lifetime of the logger and takes
+/// care of rotation.
+pub(crate) struct FileWriter {
+    queue: Arc<Queue>,
+    worker: Mutex<Option<JoinHandle<io::Result<()>>>>,
+}
+
+impl FileWriter {
+    /// Open the log file and start the writer thread
+    ///
+    /// `recover` cleans up compressions left unfinished by a previous run, see
+    /// `Compressor`.
+    pub(crate) fn spawn(
+        config: &LogConfig,
+        encoder: Arc<LineEncoder>,
+        recover: bool,
+    ) -> io::Result<FileWriter> {
+        let file = LogFile::open(config, recover)?;
+        let queue = Arc::new(Queue::new(config.overflow));
+        let worker = Worker {
+            queue: queue.clone(),
+            file,
+            encoder,
+            timestamp: config.timestamp.clone(),
+            timezone: config.timezone,
+            last_report: Instant::now(),
+        };
+        let worker = thread::Builder::new()
+            .name("moe-logger-writer".to_string())
+            .spawn(move || worker.run())?;
+        Ok(FileWriter {
+            queue,
+            worker: Mutex::new(Some(worker)),
+        })
+    }
+
+    /// Queue a rendered line for writing, applying the overflow policy if the queue is full
+    pub(crate) fn write(&self, level: Level, line: String) {
+        self.queue.push_line(level, line);
+    }
+
+    /// Block until every line queued so far is written and synced to disk
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        let (ack, done) = sync_channel(1);
+        if !self.queue.push_control(Message::Flush(ack)) {
+            return Ok(());
+        }
+        // The worker drops the acknowledgement without answering only when it exits,
+        // and it drains the queue before that.
+        done.recv().unwrap_or(Ok(()))
+    }
+
+    /// Block until every line queued so far is written, then reopen the log file
+    pub(crate) fn reopen(&self) -> io::Result<()> {
+        let (ack, done) = sync_channel(1);
+        if !self.queue.push_control(Message::Reopen(ack)) {
+            return Ok(());
+        }
+        done.recv().unwrap_or(Ok(()))
+    }
+
+    /// Drain the queue, sync the file and stop the writer thread
+    ///
+    /// Lines logged afterwards are discarded.
+    pub(crate) fn shutdown(&self) -> io::Result<()> {
+        self.queue.close();
+        match self.worker.lock().unwrap().take() {
+            Some(worker) => worker
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("log writer panicked"))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for FileWriter {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+struct Worker {
+    queue: Arc<Queue>,
+    file: LogFile,
+    encoder: Arc<LineEncoder>,
+    timestamp: Timestamp,
+    timezone: Timezone,
+    last_report: Instant,
+}
+
+impl Worker {
+    fn run(mut self) -> io::Result<()> {
+        loop {
+            let message = match self.queue.try_pop() {
+                Some(message) => message,
+                None => {
+                    // Nothing left in the queue, a good moment to hand data to the OS.
+                    if let Err(e) = self.file.flush() {
+                        eprintln!("Failed to flush log file: {}", e);
+                    }
+                    match self.queue.pop_timeout(REPORT_INTERVAL) {
+                        Pop::Message(message) => message,
+                        Pop::Timeout => {
+                            self.report_dropped();
+                            continue;
+                        }
+                        Pop::Closed => break,
+                    }
+                }
+            };
+
+            match message {
+                Message::Line(line) => self.write_line(&line),
+                Message::Flush(ack) => {
+                    let _ = ack.send(self.file.sync());
+                }
+                Message::Reopen(ack) => {
+                    let _ = ack.send(self.file.reopen());
+                }
+            }
+            self.report_dropped();
+        }
+
+        self.report_dropped();
+        self.file.sync()
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if let Err(e) = self.file.write_line(line) {
+            eprintln!("Failed to write log: {}", e);
+        }
+    }
+
+    /// Write a WARN line into the log file if records were dropped since the last report
+    fn report_dropped(&mut self) {
+        if self.last_report.elapsed() < REPORT_INTERVAL {
+            return;
+        }
+        self.last_report = Instant::now();
+
+        let dropped = self.queue.dropped.swap(0, Ordering::Relaxed);
+        if dropped == 0 {
+            return;
+        }
+        let timestamp = self.timestamp.format(self.timezone, SystemTime::now());
+        let line = self.encoder.encode(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("moe_logger")
+                .args(format_args!(
+                    "{} log records dropped, the writer fell behind",
+                    dropped
+                ))
+                .build(),
+            &timestamp,
+            &Fields::default(),
+            encode::next_seq(),
+        );
+        match line {
+            Ok(line) => self.write_line(&line),
+            Err(e) => eprintln!("Failed to render log line: {}", e),
+        }
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    rotation: usize,
+    rotation_size: u64,
+    rotation_period: Option<(RotationPeriod, Timezone)>,
+    lines: usize,
+    bytes: u64,
+    file_count: usize,
+    /// Period the current file belongs to, when rotating by time
+    period: Option<Period>,
+    retention: Retention,
+    compression: Option<Compression>,
+    compressor: Option<Compressor>,
+}
+
+impl LogFile {
+    fn open(config: &LogConfig, recover: bool) -> io::Result<LogFile> {
+        let path = config.output.clone();
+        let file = match config.open_mode {
+            OpenMode::Append | OpenMode::Roll => open_append(&path)?,
+            OpenMode::Truncate => {
+                let file = open_append(&path)?;
+                file.set_len(0)?;
+                file
+            }
+            OpenMode::Fail => OpenOptions::new()
+                .append(true)
+                .create_new(true)
+                .open(&path)?,
+        };
+
+        let metadata = file.metadata()?;
+        let period = file_period(config.rotation_period, &metadata);
+
+        let mut file = LogFile {
+            file_count: next_index(&path)?,
+            compressor: match config.compression {
+                Some(compression) => Some(Compressor::spawn(
+                    compression,
+                    path.clone(),
+                    Retention::new(config),
+                    recover,
+                )?),
+                None => None,
+            },
+            path,
+            writer: BufWriter::new(file),
+            rotation: config.rotation,
+            rotation_size: config.rotation_size,
+            rotation_period: config.rotation_period,
+            lines: 0,
+            bytes: metadata.len(),
+            period,
+            retention: Retention::new(config),
+            compression: config.compression,
+        };
+        if config.open_mode == OpenMode::Roll && file.bytes > 0 {
+            file.rotate()?;
+        } else {
+            file.prune();
+        }
+        Ok(file)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if let Some(period) = &self.period {
+            if SystemTime::now() >= period.end {
+                self.next_period();
+            }
+        }
+
+        // Rotate before the line would push the file over the size limit, a line
+        // longer than the limit still gets a file of its own.
+        let len = line.len() as u64;
+        if self.rotation_size > 0 && self.bytes > 0 && self.bytes + len > self.rotation_size {
+            self.try_rotate();
+        }
+
+        self.writer.write_all(line.as_bytes())?;
+        self.lines += 1;
+        self.bytes += len;
+
+        if self.lines == self.rotation {
+            self.try_rotate();
+        }
+        Ok(())
+    }
+
+    /// Rotate the file of a finished period, an empty file simply moves on to the new period
+    fn next_period(&mut self) {
+        if self.bytes > 0 {
+            self.try_rotate();
+        } else {
+            self.update_period();
+        }
+    }
+
+    fn update_period(&mut self) {
+        if let Some((period, timezone)) = self.rotation_period {
+            self.period = Some(Period::at(period, timezone, SystemTime::now()));
+        }
+    }
+
+    fn try_rotate(&mut self) {
+        if let Err(e) = self.rotate() {
+            eprintln!("Failed to rotate log: {}", e);
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let archive = self.archive_name();
+        rename(&self.path, &archive)?;
+        self.writer = BufWriter::new(open_append(&self.path)?);
+        self.file_count += 1;
+        self.lines = 0;
+        self.bytes = 0;
+        self.update_period();
+        match &self.compressor {
+            Some(compressor) => compressor.compress(archive),
+            None => self.prune(),
+        }
+        Ok(())
+    }
+
+    /// Whether a rotated file already uses this name, compressed or not
+    fn is_taken(&self, name: &Path) -> bool {
+        name.exists()
+            || self
+                .compression
+                .is_some_and(|compression| with_suffix(name, compression.extension()).exists())
+    }
+
+    fn prune(&self) {
+        if let Err(e) = self.retention.prune(&self.path) {
+            eprintln!("Failed to remove old logs: {}", e);
+        }
+    }
+
+    /// Name for the next rotated file
+    ///
+    /// Rotated files are named after their period when rotating by time, a counter is
+    /// appended if the period already has one. Otherwise they are simply numbered.
+    fn archive_name(&self) -> PathBuf {
+        let period = match &self.period {
+            Some(period) => period,
+            None => return with_suffix(&self.path, self.file_count),
+        };
+        let base = with_suffix(&self.path, &period.label);
+        let mut name = base.clone();
+        let mut count = 1;
+        while self.is_taken(&name) {
+            name = with_suffix(&base, count);
+            count += 1;
+        }
+        name
+    }
+
+    /// Close the file and open `path` again, for when another tool moved or truncated it
+    ///
+    /// Size and line counts start over from whatever is at `path` now.
+    fn reopen(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let file = open_append(&self.path)?;
+        let metadata = file.metadata()?;
+        self.writer = BufWriter::new(file);
+        self.lines = 0;
+        self.bytes = metadata.len();
+        self.period = file_period(self.rotation_period, &metadata);
+        // The other tool may have used our archive names.
+        self.file_count = next_index(&self.path)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flush buffered lines and make sure they reach the disk
+    fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()
+    }
+}
+
+/// Period of an opened file, old content belongs to the period it was written in
+fn file_period(
+    rotation_period: Option<(RotationPeriod, Timezone)>,
+    metadata: &Metadata,
+) -> Option<Period> {
+    rotation_period.map(|(period, timezone)| {
+        let at = match metadata.modified() {
+            Ok(modified) if metadata.len() > 0 => modified,
+            _ => SystemTime::now(),
+        };
+        Period::at(period, timezone, at)
+    })
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
@@ -0,0 +1,12 @@
+//! Helpers shared by the integration tests
+
+use std::fs;
+use std::path::PathBuf;
+
+/// An empty directory for the test named `name`, unique to this process
+pub fn log_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("moe-logger-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "gzip")]
+
+mod common;
+
+use flate2::read::GzDecoder;
+use log::info;
+use moe_logger::{Compression, LogConfig};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+fn gunzip(path: &Path) -> String {
+    let mut text = String::new();
+    GzDecoder::new(File::open(path).unwrap())
+        .read_to_string(&mut text)
+        .unwrap();
+    text
+}
+
+#[test]
+fn compression_with_max_files_and_leftovers() {
+    let dir = common::log_dir("compression");
+    let output = dir.join("run.log");
+
+    // Left by a run that stopped while compressing. The leftover archive is dated in
+    // the future, so retention keeps it and we can check it was compressed.
+    fs::write(dir.join("run.log.2.gz.tmp"), "half written").unwrap();
+    let leftover = File::create(dir.join("run.log.1")).unwrap();
+    (&leftover).write_all(b"from the last run\n").unwrap();
+    leftover
+        .set_modified(SystemTime::now() + Duration::from_secs(3600))
+        .unwrap();
+    drop(leftover);
+
+    let config = LogConfig::builder()
+        .env("MOE_LOGGER_TEST_LEVEL")
+        .output(&output)
+        .format("{M}\n")
+        .rotation(1)
+        .compression(Compression::Gzip)
+        .max_files(3)
+        .finish();
+    let handle = moe_logger::init(config);
+    for i in 0..4 {
+        info!("line {}", i);
+    }
+    handle.shutdown().unwrap();
+
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        ["run.log", "run.log.1.gz", "run.log.4.gz", "run.log.5.gz"]
+    );
+    assert_eq!(gunzip(&dir.join("run.log.1.gz")), "from the last run\n");
+    assert_eq!(gunzip(&dir.join("run.log.4.gz")), "line 2\n");
+    assert_eq!(gunzip(&dir.join("run.log.5.gz")), "line 3\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
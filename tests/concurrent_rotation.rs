@@ -0,0 +1,70 @@
+mod common;
+
+use log::info;
+use moe_logger::LogConfig;
+use std::collections::HashSet;
+use std::fs;
+use std::thread;
+
+const THREADS: usize = 8;
+const LINES_PER_THREAD: usize = 500;
+const ROTATION: usize = 300;
+
+#[test]
+fn concurrent_writes_across_rotations() {
+    let dir = common::log_dir("rotation");
+    let output = dir.join("run.log");
+
+    let config = LogConfig::builder()
+        .env("MOE_LOGGER_TEST_LEVEL")
+        .output(&output)
+        .format("{M}\n")
+        .rotation(ROTATION)
+        .finish();
+    let handle = moe_logger::init(config);
+
+    let threads: Vec<_> = (0..THREADS)
+        .map(|t| {
+            thread::spawn(move || {
+                for i in 0..LINES_PER_THREAD {
+                    info!("thread {} line {}", t, i);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    handle.shutdown().unwrap();
+
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    assert_eq!(files.len(), THREADS * LINES_PER_THREAD / ROTATION + 1);
+
+    let mut seen = HashSet::new();
+    for file in &files {
+        let content = fs::read_to_string(file).unwrap();
+        assert!(!content.contains('\0'), "{} has holes", file.display());
+
+        let lines: Vec<_> = content.lines().collect();
+        if *file != output {
+            assert_eq!(lines.len(), ROTATION, "{} was not rotated", file.display());
+        }
+        for line in lines {
+            assert!(seen.insert(line.to_string()), "{} written twice", line);
+        }
+    }
+
+    for t in 0..THREADS {
+        for i in 0..LINES_PER_THREAD {
+            let line = format!("thread {} line {}", t, i);
+            assert!(seen.contains(&line), "{} is missing", line);
+        }
+    }
+    assert_eq!(seen.len(), THREADS * LINES_PER_THREAD);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
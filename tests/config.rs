@@ -0,0 +1,168 @@
+#![cfg(feature = "config")]
+
+mod common;
+
+use moe_logger::{
+    Console, Encoding, LogConfig, MoeLoggerError, RotationPeriod, Timestamp, Timezone,
+};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn config_file(name: &str, text: &str) -> PathBuf {
+    let path = common::log_dir(&format!("config-{}", name)).join(name);
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn rejected(result: Result<LogConfig, MoeLoggerError>) -> MoeLoggerError {
+    match result {
+        Ok(_) => panic!("invalid config was accepted"),
+        Err(e) => e,
+    }
+}
+
+fn json(text: &str) -> Result<LogConfig, String> {
+    serde_json::from_str(text).map_err(|e| e.to_string())
+}
+
+fn max_age(value: &str) -> Result<Option<Duration>, String> {
+    json(&format!(r#"{{"max_age": {}}}"#, value)).map(|config| config.max_age)
+}
+
+#[test]
+fn from_json_file() {
+    let path = config_file(
+        "log.json",
+        r#"{
+            "level": "warn,app=debug",
+            "output": "/var/log/app/run.log",
+            "encoding": "json",
+            "timestamp": {"pattern": "%H:%M"},
+            "console": "off",
+            "rotation_period": "daily",
+            "rotation_timezone": "local",
+            "max_age": "7d",
+            "sinks": [{"output": "error.log", "filter": "error"}]
+        }"#,
+    );
+    let config = LogConfig::from_file(&path).unwrap();
+    assert_eq!(config.filter, "warn,app=debug");
+    assert_eq!(config.output, PathBuf::from("/var/log/app/run.log"));
+    assert!(config.file);
+    assert_eq!(config.encoding, Encoding::Json);
+    assert_eq!(config.timestamp, Timestamp::Pattern("%H:%M".to_string()));
+    assert_eq!(config.console, Console::Off);
+    assert_eq!(
+        config.rotation_period,
+        Some((RotationPeriod::Daily, Timezone::Local))
+    );
+    assert_eq!(config.max_age, Some(Duration::from_secs(7 * 24 * 60 * 60)));
+    assert_eq!(config.sinks.len(), 1);
+    assert_eq!(config.sinks[0].output, PathBuf::from("error.log"));
+    assert_eq!(config.sinks[0].filter, "error");
+    // Unset fields keep their defaults.
+    assert_eq!(config.env, "RUST_LOG");
+    assert_eq!(config.rotation, 0);
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn from_toml_file() {
+    let path = config_file(
+        "log.toml",
+        "filter = \"debug\"\nrotation_size = 1048576\n\n[[sinks]]\noutput = \"error.log\"\n",
+    );
+    let config = LogConfig::from_file(&path).unwrap();
+    assert_eq!(config.filter, "debug");
+    assert_eq!(config.rotation_size, 1 << 20);
+    assert_eq!(config.sinks[0].output, PathBuf::from("error.log"));
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn from_yaml_file() {
+    let path = config_file("log.yml", "filter: debug\nmax_files: 5\n");
+    let config = LogConfig::from_file(&path).unwrap();
+    assert_eq!(config.filter, "debug");
+    assert_eq!(config.max_files, 5);
+}
+
+#[test]
+fn file_errors() {
+    let path = config_file("unknown.json", r#"{"rotate": 10}"#);
+    let error = rejected(LogConfig::from_file(&path));
+    assert!(matches!(error, MoeLoggerError::Config(_)));
+    let error = error.to_string();
+    assert!(error.contains("unknown.json"), "{}", error);
+    assert!(error.contains("rotate"), "{}", error);
+
+    let path = config_file("format.json", r#"{"format": "{M"}"#);
+    let error = rejected(LogConfig::from_file(&path));
+    assert!(matches!(error, MoeLoggerError::Config(_)));
+    let error = error.to_string();
+    assert!(error.contains("invalid format"), "{}", error);
+
+    let path = config_file("log.ini", "filter = debug");
+    let error = rejected(LogConfig::from_file(&path)).to_string();
+    assert!(error.contains("unsupported config format"), "{}", error);
+
+    let error = rejected(LogConfig::from_file("/nonexistent/log.json"));
+    assert!(matches!(error, MoeLoggerError::Config(_)));
+}
+
+#[test]
+fn from_env_prefix() {
+    std::env::set_var("MOE_LOGGER_TEST_CFG_OUTPUT", "run.log");
+    std::env::set_var("MOE_LOGGER_TEST_CFG_ROTATION", "100");
+    std::env::set_var("MOE_LOGGER_TEST_CFG_MAX_AGE", "15m");
+    std::env::set_var("MOE_LOGGER_TEST_CFG_TIMESTAMP", r#"{"pattern":"%H:%M"}"#);
+    let config = LogConfig::from_env_prefix("MOE_LOGGER_TEST_CFG_").unwrap();
+    assert_eq!(config.output, PathBuf::from("run.log"));
+    assert!(config.file);
+    assert_eq!(config.rotation, 100);
+    assert_eq!(config.max_age, Some(Duration::from_secs(15 * 60)));
+    assert_eq!(config.timestamp, Timestamp::Pattern("%H:%M".to_string()));
+
+    std::env::set_var("MOE_LOGGER_TEST_BAD_ROTATION", "many");
+    let error = rejected(LogConfig::from_env_prefix("MOE_LOGGER_TEST_BAD_"));
+    assert!(
+        error.to_string().contains("MOE_LOGGER_TEST_BAD_*"),
+        "{}",
+        error
+    );
+
+    std::env::set_var("MOE_LOGGER_TEST_FMT_FORMAT", "{M");
+    let error = rejected(LogConfig::from_env_prefix("MOE_LOGGER_TEST_FMT_"));
+    assert!(error.to_string().contains("invalid format"), "{}", error);
+}
+
+#[test]
+fn durations() {
+    assert_eq!(max_age("90"), Ok(Some(Duration::from_secs(90))));
+    assert_eq!(max_age(r#""90""#), Ok(Some(Duration::from_secs(90))));
+    assert_eq!(max_age(r#""30s""#), Ok(Some(Duration::from_secs(30))));
+    assert_eq!(max_age(r#""15m""#), Ok(Some(Duration::from_secs(15 * 60))));
+    assert_eq!(
+        max_age(r#""12h""#),
+        Ok(Some(Duration::from_secs(12 * 60 * 60)))
+    );
+    assert_eq!(
+        max_age(r#""2w""#),
+        Ok(Some(Duration::from_secs(14 * 24 * 60 * 60)))
+    );
+}
+
+#[test]
+fn invalid_durations() {
+    for value in [
+        r#""7y""#,
+        r#""d""#,
+        r#""-1d""#,
+        r#""""#,
+        r#""99999999999999999w""#,
+    ] {
+        let error = max_age(value).unwrap_err();
+        assert!(error.contains("invalid duration"), "{}: {}", value, error);
+    }
+}
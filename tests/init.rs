@@ -0,0 +1,60 @@
+mod common;
+
+use log::info;
+use moe_logger::{Console, LogConfig, MoeLoggerError, OpenMode, RotationPeriod, Timezone};
+use std::fs;
+use std::path::Path;
+
+fn config(output: &Path) -> LogConfig {
+    LogConfig::builder()
+        .env("MOE_LOGGER_TEST_LEVEL")
+        .console(Console::Off)
+        .output(output)
+        .format("{M}\n")
+        .open_mode(OpenMode::Roll)
+        .finish()
+}
+
+#[test]
+fn second_init_leaves_the_files_alone() {
+    let dir = common::log_dir("init");
+    let output = dir.join("run.log");
+    let handle = moe_logger::init(config(&output));
+    info!("first");
+    handle.flush().unwrap();
+
+    let error = moe_logger::try_init(config(&output)).err().unwrap();
+    assert!(
+        matches!(error, MoeLoggerError::AlreadyInitialized(_)),
+        "{}",
+        error
+    );
+    info!("second");
+    handle.shutdown().unwrap();
+
+    assert_eq!(fs::read_to_string(&output).unwrap(), "first\nsecond\n");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn zero_minute_rotation_is_rejected() {
+    let dir = std::env::temp_dir();
+    let every_zero_minutes = |output: &str| {
+        LogConfig::builder()
+            .output(dir.join(output))
+            .rotation_period(RotationPeriod::Minutes(0), Timezone::Utc)
+            .finish()
+    };
+
+    let main = every_zero_minutes("moe-logger-zero-minutes.log");
+    let sink = LogConfig::builder()
+        .console(Console::Off)
+        .sink(every_zero_minutes("moe-logger-zero-minutes.log"))
+        .finish();
+    for config in [main, sink] {
+        let error = moe_logger::try_init(config).err().unwrap();
+        assert!(matches!(error, MoeLoggerError::Config(_)), "{}", error);
+        assert!(error.to_string().contains("0 minutes"), "{}", error);
+    }
+}
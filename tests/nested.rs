@@ -0,0 +1,56 @@
+mod common;
+
+use log::info;
+use moe_logger::{Console, Handle, LogConfig};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+fn config(output: &Path) -> LogConfig {
+    LogConfig::builder()
+        .env("MOE_LOGGER_TEST_LEVEL")
+        .console(Console::Off)
+        .output(output)
+        .format("{M}\n")
+        .finish()
+}
+
+/// Changes the config from another thread while being formatted, then logs itself
+struct Reconfigure<'a> {
+    handle: &'a Handle,
+    output: &'a Path,
+}
+
+impl fmt::Display for Reconfigure<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        thread::scope(|scope| {
+            scope.spawn(|| self.handle.set_filter("trace"));
+        });
+        thread::scope(|scope| {
+            scope.spawn(|| self.handle.reload(config(self.output)).unwrap());
+        });
+        info!("nested");
+        f.write_str("outer")
+    }
+}
+
+#[test]
+fn logging_while_formatting_during_reconfiguration() {
+    let dir = common::log_dir("nested");
+    let output = dir.join("run.log");
+    let handle = moe_logger::init(config(&output));
+
+    info!(
+        "{}",
+        Reconfigure {
+            handle: &handle,
+            output: &output,
+        }
+    );
+    handle.flush().unwrap();
+    handle.shutdown().unwrap();
+
+    assert_eq!(fs::read_to_string(&output).unwrap(), "nested\nouter\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use log::info;
+use moe_logger::{Console, LogConfig, MoeLoggerError, Timestamp};
+use std::fs;
+use std::path::Path;
+
+fn config(output: &Path, format: &str) -> LogConfig {
+    LogConfig::builder()
+        .env("MOE_LOGGER_TEST_LEVEL")
+        .console(Console::Off)
+        .output(output)
+        .format(format)
+        .finish()
+}
+
+#[test]
+fn invalid_reload_keeps_the_previous_config() {
+    let dir = common::log_dir("reload");
+    let output = dir.join("run.log");
+    let handle = moe_logger::init(config(&output, "old {M}\n"));
+    info!("before");
+    log::logger().flush();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "old before\n");
+
+    // Rejected before anything is stopped.
+    let invalid = LogConfig {
+        timestamp: Timestamp::Pattern("%Q".to_string()),
+        ..config(&output, "new {M}\n")
+    };
+    let error = handle.reload(invalid).unwrap_err();
+    assert!(matches!(error, MoeLoggerError::Timestamp(_)), "{}", error);
+    info!("after invalid timestamp");
+
+    let error = handle.reload(config(&output, "new {M\n")).unwrap_err();
+    assert!(matches!(error, MoeLoggerError::Template(_)), "{}", error);
+    info!("after invalid format");
+
+    // Fails while opening the file.
+    let missing_dir = dir.join("missing").join("run.log");
+    let error = handle
+        .reload(config(&missing_dir, "new {M}\n"))
+        .unwrap_err();
+    assert!(matches!(error, MoeLoggerError::Io(_)), "{}", error);
+    info!("after missing directory");
+
+    handle.reload(config(&output, "new {M}\n")).unwrap();
+    info!("after reload");
+    handle.shutdown().unwrap();
+
+    assert_eq!(
+        fs::read_to_string(&output).unwrap(),
+        "old before\nold after invalid timestamp\nold after invalid format\nold after missing directory\nnew after reload\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
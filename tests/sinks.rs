@@ -0,0 +1,102 @@
+mod common;
+
+use log::{debug, error, info, warn};
+use moe_logger::{Console, Encoding, LogConfig, MoeLoggerError};
+use std::fs;
+
+#[test]
+fn sinks_filter_and_format_independently() {
+    let dir = common::log_dir("sinks");
+    let app = dir.join("app.log");
+    let errors = dir.join("error.log");
+
+    let config = LogConfig::builder()
+        .env("MOE_LOGGER_TEST_LEVEL")
+        .console(Console::Off)
+        .sink(
+            LogConfig::builder()
+                .filter("debug")
+                .output(&app)
+                .format("{L} {M}\n")
+                .finish(),
+        )
+        .sink(
+            LogConfig::builder()
+                .filter("warn")
+                .output(&errors)
+                .encoding(Encoding::Json)
+                .finish(),
+        )
+        .finish();
+    let handle = moe_logger::init(config);
+
+    debug!("connecting");
+    info!("started");
+    warn!("slow query");
+    error!("connection lost");
+    handle.shutdown().unwrap();
+
+    let app = fs::read_to_string(&app).unwrap();
+    assert_eq!(
+        app,
+        "DEBUG connecting\nINFO started\nWARN slow query\nERROR connection lost\n"
+    );
+
+    let errors = fs::read_to_string(&errors).unwrap();
+    let levels: Vec<_> = errors
+        .lines()
+        .map(|line| {
+            let line: serde_json::Value = serde_json::from_str(line).unwrap();
+            (line["level"].clone(), line["message"].clone())
+        })
+        .collect();
+    assert_eq!(
+        levels,
+        [
+            ("warn".into(), "slow query".into()),
+            ("error".into(), "connection lost".into())
+        ]
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn rejected(config: LogConfig) -> String {
+    match moe_logger::try_init(config) {
+        Ok(_) => panic!("overlapping outputs were accepted"),
+        Err(MoeLoggerError::Config(e)) => e,
+        Err(e) => panic!("unexpected error: {}", e),
+    }
+}
+
+fn with_sink(output: &str, sink: &str) -> LogConfig {
+    LogConfig::builder()
+        .output(output)
+        .sink(LogConfig::builder().output(sink).finish())
+        .finish()
+}
+
+#[test]
+fn overlapping_outputs_are_rejected() {
+    let error = rejected(with_sink("app.log", "./app.log"));
+    assert!(error.contains("same file"), "{}", error);
+
+    let error = rejected(with_sink("app.log", "app.log.1"));
+    assert!(error.contains("rotated files"), "{}", error);
+    let error = rejected(with_sink("./app.log.2024-03-10.gz", "app.log"));
+    assert!(error.contains("rotated files"), "{}", error);
+
+    let error = rejected(LogConfig::builder().sink(LogConfig::default()).finish());
+    assert!(error.contains("no output"), "{}", error);
+
+    let nested = LogConfig::builder()
+        .sink(
+            LogConfig::builder()
+                .output("a.log")
+                .sink(LogConfig::builder().output("b.log").finish())
+                .finish(),
+        )
+        .finish();
+    let error = rejected(nested);
+    assert!(error.contains("sinks of their own"), "{}", error);
+}